@@ -1,12 +1,21 @@
-use clap::Parser;
-use std::env;
-use std::fmt::{self, Write as _};
-use std::io::{self, Write as _};
+use clap::{Parser, ValueEnum};
+use rand::rngs::OsRng;
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair, KeyUsagePurpose, SanType, SignatureAlgorithm, PKCS_ECDSA_P256_SHA256, PKCS_ED25519,
+    PKCS_RSA_SHA256,
+};
+use rsa::pkcs8::EncodePrivateKey;
+use rsa::RsaPrivateKey;
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions, Permissions};
+use std::io::{self, Write};
 use std::net::IpAddr;
-use std::path::{Path, PathBuf};
-use std::process::{Command, ExitCode, ExitStatus};
-use tempfile::NamedTempFile;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+use std::process::ExitCode;
 use thiserror::Error;
+use time::{Duration, OffsetDateTime};
 
 #[derive(Parser)]
 #[clap(
@@ -37,22 +46,82 @@ struct Args {
         default_value = "365"
     )]
     days: u32,
+    #[clap(
+        long,
+        short,
+        help = "Key algorithm to use",
+        value_enum,
+        default_value = "ecdsa-p256"
+    )]
+    algorithm: Algorithm,
+    #[clap(
+        long,
+        short = 'n',
+        help = "Common name of the certificate",
+        default_value = "rkvm"
+    )]
+    common_name: String,
+    #[clap(
+        long,
+        help = "Generate a CA certificate for signing other certificates",
+        conflicts_with_all = ["client", "ca_certificate"]
+    )]
+    ca: bool,
+    #[clap(
+        long,
+        help = "Generate a client certificate, identified by its common name, rather than a server one"
+    )]
+    client: bool,
+    #[clap(
+        long,
+        help = "Sign the certificate with this CA certificate rather than self-signing it",
+        requires = "ca_key"
+    )]
+    ca_certificate: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Private key of the CA certificate",
+        requires = "ca_certificate"
+    )]
+    ca_key: Option<PathBuf>,
+}
+
+impl Args {
+    fn kind(&self) -> Kind {
+        match (self.ca, self.client) {
+            (true, _) => Kind::Ca,
+            (false, true) => Kind::Client,
+            (false, false) => Kind::Server,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum Algorithm {
+    EcdsaP256,
+    Ed25519,
+    Rsa,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Ca,
+    Server,
+    Client,
 }
 
 fn main() -> ExitCode {
     let args = Args::parse();
-    if args.dns_names.is_empty() && args.ip_addresses.is_empty() {
-        eprintln!("No DNS names or IP addresses were provided");
-        return ExitCode::FAILURE;
+    let kind = args.kind();
+
+    if let Kind::Server = kind {
+        if args.dns_names.is_empty() && args.ip_addresses.is_empty() {
+            eprintln!("No DNS names or IP addresses were provided");
+            return ExitCode::FAILURE;
+        }
     }
 
-    let result = run(
-        &args.certificate,
-        &args.key,
-        &args.dns_names,
-        &args.ip_addresses,
-        args.days,
-    );
+    let result = run(&args, kind);
 
     match result {
         Ok(()) => ExitCode::SUCCESS,
@@ -68,64 +137,234 @@ enum Error {
     #[error(transparent)]
     Io(#[from] io::Error),
     #[error(transparent)]
-    Fmt(#[from] fmt::Error),
-    #[error("Bad exit: {0}")]
-    BadExit(ExitStatus),
+    Rcgen(#[from] rcgen::Error),
+    #[error(transparent)]
+    Rsa(#[from] rsa::Error),
+    #[error(transparent)]
+    Pkcs8(#[from] rsa::pkcs8::Error),
+    #[error(transparent)]
+    Pem(#[from] pem::PemError),
 }
 
-fn run(
-    certificate: &Path,
-    key: &Path,
-    dns_names: &[String],
-    ip_addresses: &[IpAddr],
-    days: u32,
-) -> Result<(), Error> {
-    let mut config = "[req]
-prompt = no
-default_bits = 2048
-distinguished_name = req_distinguished_name
-req_extensions = req_ext
-x509_extensions = v3_req
-[req_distinguished_name]
-commonName = rkvm
-[req_ext]
-subjectAltName = @alt_names
-[v3_req]
-subjectAltName = @alt_names
-[alt_names]"
-        .to_owned();
-    for (i, name) in dns_names.iter().enumerate() {
-        write!(config, "\nDNS.{} = {}", i + 1, name)?;
+fn run(args: &Args, kind: Kind) -> Result<(), Error> {
+    let algorithm = args.algorithm;
+
+    let mut params = CertificateParams::default();
+    params.alg = algorithm.signature();
+    params.key_pair = Some(algorithm.generate()?);
+    params
+        .distinguished_name
+        .push(DnType::CommonName, args.common_name.as_str());
+
+    params.not_before = OffsetDateTime::now_utc();
+    params.not_after = params.not_before + Duration::days(args.days.into());
+
+    params.subject_alt_names = args
+        .dns_names
+        .iter()
+        .cloned()
+        .map(SanType::DnsName)
+        .chain(args.ip_addresses.iter().copied().map(SanType::IpAddress))
+        .collect();
+
+    params.key_usages = match kind {
+        Kind::Ca => vec![
+            KeyUsagePurpose::DigitalSignature,
+            KeyUsagePurpose::KeyCertSign,
+            KeyUsagePurpose::CrlSign,
+        ],
+        Kind::Server | Kind::Client => match algorithm {
+            // RSA key exchange encrypts with the certificate's key.
+            Algorithm::Rsa => vec![
+                KeyUsagePurpose::DigitalSignature,
+                KeyUsagePurpose::KeyEncipherment,
+            ],
+            Algorithm::EcdsaP256 | Algorithm::Ed25519 => vec![KeyUsagePurpose::DigitalSignature],
+        },
+    };
+
+    params.extended_key_usages = match kind {
+        Kind::Ca => Vec::new(),
+        Kind::Server => vec![ExtendedKeyUsagePurpose::ServerAuth],
+        Kind::Client => vec![ExtendedKeyUsagePurpose::ClientAuth],
+    };
+
+    if let Kind::Ca = kind {
+        // Only leaf certificates are signed by the CA, there are no intermediate ones.
+        params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
     }
 
-    for (i, address) in ip_addresses.iter().enumerate() {
-        write!(config, "\nIP.{} = {}", i + 1, address)?;
+    let generated = Certificate::from_params(params)?;
+
+    let pem = match (&args.ca_certificate, &args.ca_key) {
+        (Some(ca_certificate), Some(ca_key)) => {
+            let ca_key = KeyPair::from_pem(&fs::read_to_string(ca_key)?)?;
+            let ca_params =
+                CertificateParams::from_ca_cert_pem(&fs::read_to_string(ca_certificate)?, ca_key)?;
+            let ca = Certificate::from_params(ca_params)?;
+
+            generated.serialize_pem_with_signer(&ca)?
+        }
+        _ => generated.serialize_pem()?,
+    };
+
+    fs::write(&args.certificate, &pem)?;
+
+    // The key must not be readable by anyone else, including when overwriting an existing file.
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&args.key)?;
+
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    file.write_all(generated.serialize_private_key_pem().as_bytes())?;
+
+    // Clients can pin the certificate using this instead of copying it over.
+    let fingerprint = Sha256::digest(pem::parse(&pem)?.contents());
+    let fingerprint = fingerprint
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    println!("Fingerprint: sha256:{}", fingerprint);
+
+    Ok(())
+}
+
+impl Algorithm {
+    fn signature(&self) -> &'static SignatureAlgorithm {
+        match self {
+            Self::EcdsaP256 => &PKCS_ECDSA_P256_SHA256,
+            Self::Ed25519 => &PKCS_ED25519,
+            Self::Rsa => &PKCS_RSA_SHA256,
+        }
     }
 
-    let mut file = NamedTempFile::new()?;
-    file.write_all(config.as_bytes())?;
-
-    let openssl = env::var_os("OPENSSL").unwrap_or_else(|| "openssl".to_owned().into());
-    let status = Command::new(&openssl)
-        .arg("req")
-        .arg("-sha256")
-        .arg("-x509")
-        .arg("-nodes")
-        .arg("-newkey")
-        .arg("rsa:2048")
-        .arg("-keyout")
-        .arg(key)
-        .arg("-out")
-        .arg(certificate)
-        .arg("-config")
-        .arg(file.path())
-        .arg("-days")
-        .arg(days.to_string())
-        .status()?;
-
-    if !status.success() {
-        return Err(Error::BadExit(status));
+    fn generate(&self) -> Result<KeyPair, Error> {
+        let key = match self {
+            // Ring can't generate RSA keys.
+            Self::Rsa => {
+                let key = RsaPrivateKey::new(&mut OsRng, 2048)?;
+                let key = key.to_pkcs8_der()?;
+
+                KeyPair::from_der_and_sign_algo(key.as_bytes(), self.signature())?
+            }
+            Self::EcdsaP256 | Self::Ed25519 => KeyPair::generate(self.signature())?,
+        };
+
+        Ok(key)
     }
+}
 
-    Ok(())
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rustls::client::{ServerCertVerifier, WebPkiVerifier};
+    use rustls::server::{AllowAnyAuthenticatedClient, ClientCertVerifier};
+    use rustls::{RootCertStore, ServerName};
+    use std::env;
+    use std::path::Path;
+    use std::process;
+    use std::time::SystemTime;
+    use x509_parser::certificate::X509Certificate;
+
+    fn generate(directory: &Path, name: &str, extra: &[&str]) -> rustls::Certificate {
+        let certificate = directory.join(format!("{}.pem", name));
+        let key = directory.join(format!("{}.key", name));
+
+        let args = Args::try_parse_from(
+            ["rkvm-certificate-gen"]
+                .into_iter()
+                .chain(extra.iter().copied())
+                .chain([certificate.to_str().unwrap(), key.to_str().unwrap()]),
+        )
+        .unwrap();
+
+        run(&args, args.kind()).unwrap();
+
+        let pem = fs::read(&certificate).unwrap();
+        let mut certificates = rustls_pemfile::certs(&mut pem.as_slice()).unwrap();
+        assert_eq!(certificates.len(), 1);
+
+        rustls::Certificate(certificates.remove(0))
+    }
+
+    fn parse(certificate: &rustls::Certificate) -> X509Certificate<'_> {
+        x509_parser::parse_x509_certificate(&certificate.0)
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn leaves_verify_against_ca() {
+        let directory = env::temp_dir().join(format!("rkvm-certificate-gen-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        let ca_certificate = directory.join("ca.pem");
+        let ca_key = directory.join("ca.key");
+        let signed = [
+            "--ca-certificate",
+            ca_certificate.to_str().unwrap(),
+            "--ca-key",
+            ca_key.to_str().unwrap(),
+        ];
+
+        let ca = generate(&directory, "ca", &["--ca", "-a", "ed25519"]);
+        let server = generate(
+            &directory,
+            "server",
+            &[&signed[..], &["-d", "myserver.local"]].concat(),
+        );
+        let client = generate(
+            &directory,
+            "client",
+            &[&signed[..], &["--client", "-n", "laptop"]].concat(),
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+
+        let ca_parsed = parse(&ca);
+        let key_usage = ca_parsed.key_usage().unwrap().unwrap().value;
+        assert!(ca_parsed.is_ca());
+        assert!(key_usage.key_cert_sign() && key_usage.crl_sign());
+
+        for (certificate, server_auth) in [(&server, true), (&client, false)] {
+            let parsed = parse(certificate);
+            let key_usage = parsed.key_usage().unwrap().unwrap().value;
+            let extended_key_usage = parsed.extended_key_usage().unwrap().unwrap().value;
+
+            assert!(!parsed.is_ca());
+            assert!(key_usage.digital_signature() && !key_usage.key_cert_sign());
+            assert_eq!(extended_key_usage.server_auth, server_auth);
+            assert_eq!(extended_key_usage.client_auth, !server_auth);
+        }
+
+        let mut store = RootCertStore::empty();
+        store.add(&ca).unwrap();
+
+        // What the client checks the server with when given the CA.
+        let verifier = WebPkiVerifier::new(store.clone(), None);
+        let verify = |certificate| {
+            verifier.verify_server_cert(
+                certificate,
+                &[],
+                &ServerName::try_from("myserver.local").unwrap(),
+                &mut std::iter::empty(),
+                &[],
+                SystemTime::now(),
+            )
+        };
+
+        assert!(verify(&server).is_ok());
+        assert!(verify(&client).is_err());
+
+        // What the server checks clients with when given the CA.
+        let verifier = AllowAnyAuthenticatedClient::new(store);
+        let verify = |certificate| verifier.verify_client_cert(certificate, &[], SystemTime::now());
+
+        assert!(verify(&client).is_ok());
+        assert!(verify(&server).is_err());
+    }
 }
@@ -0,0 +1,159 @@
+use rand::Rng;
+use std::time::Duration;
+
+// How reconnecting is delayed after an attempt fails, all delays are in seconds.
+#[derive(Clone, Copy)]
+pub struct Reconnect {
+    pub initial_delay: f64,
+    pub max_delay: f64,
+    pub multiplier: f64,
+    // Fraction of the delay it randomly varies by.
+    pub jitter: f64,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            initial_delay: 0.5,
+            max_delay: 30.0,
+            multiplier: 2.0,
+            jitter: 0.1,
+        }
+    }
+}
+
+impl Reconnect {
+    // Durations can't be built from negative, infinite or NaN values, or ones too large to represent.
+    pub(crate) fn is_valid(&self) -> bool {
+        Duration::try_from_secs_f64(self.initial_delay).is_ok()
+            && Duration::try_from_secs_f64(self.max_delay).is_ok()
+            && self.multiplier.is_finite()
+            && self.jitter.is_finite()
+    }
+}
+
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    multiplier: f64,
+    jitter: f64,
+    current: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(config: &Reconnect) -> Self {
+        let initial = Duration::from_secs_f64(config.initial_delay);
+
+        Self {
+            initial,
+            max: Duration::from_secs_f64(config.max_delay).max(initial),
+            multiplier: config.multiplier.max(1.0),
+            jitter: config.jitter.clamp(0.0, 1.0),
+            current: initial,
+            attempt: 0,
+        }
+    }
+
+    // Returns the number of the upcoming attempt and how long to wait before it.
+    pub fn next(&mut self) -> (u32, Duration) {
+        let delay = self.current;
+
+        self.current = Duration::try_from_secs_f64(self.current.as_secs_f64() * self.multiplier)
+            .map_or(self.max, |current| current.min(self.max));
+        self.attempt += 1;
+
+        // Spread the delay to keep clients from reconnecting in lockstep after a server restart.
+        // Jitter can push delays close to the limit past what a Duration can hold.
+        let factor = 1.0 + self.jitter * rand::thread_rng().gen_range(-1.0..=1.0);
+        let delay = Duration::try_from_secs_f64(delay.as_secs_f64() * factor).unwrap_or(self.max);
+
+        (self.attempt, delay)
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+        self.attempt = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(jitter: f64) -> Reconnect {
+        Reconnect {
+            initial_delay: 0.5,
+            max_delay: 4.0,
+            multiplier: 2.0,
+            jitter,
+        }
+    }
+
+    #[test]
+    fn grows_exponentially_up_to_max() {
+        let mut backoff = Backoff::new(&config(0.0));
+        let delays = (0..6).map(|_| backoff.next()).collect::<Vec<_>>();
+
+        assert_eq!(
+            delays,
+            [
+                (1, Duration::from_millis(500)),
+                (2, Duration::from_secs(1)),
+                (3, Duration::from_secs(2)),
+                (4, Duration::from_secs(4)),
+                (5, Duration::from_secs(4)),
+                (6, Duration::from_secs(4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unrepresentable_delays() {
+        assert!(config(0.0).is_valid());
+
+        for delay in [-1.0, f64::NAN, f64::INFINITY, 1e300] {
+            let reconnect = Reconnect {
+                max_delay: delay,
+                ..config(0.0)
+            };
+
+            assert!(!reconnect.is_valid());
+        }
+
+        // Valid, but doubling it with jitter overflows.
+        let reconnect = Reconnect {
+            initial_delay: 1.8e19,
+            max_delay: 1.8e19,
+            ..config(1.0)
+        };
+        assert!(reconnect.is_valid());
+
+        let mut backoff = Backoff::new(&reconnect);
+        for _ in 0..100 {
+            backoff.next();
+        }
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut backoff = Backoff::new(&config(0.0));
+        backoff.next();
+        backoff.next();
+        backoff.reset();
+
+        assert_eq!(backoff.next(), (1, Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn jitter_stays_in_bounds() {
+        let mut backoff = Backoff::new(&config(0.25));
+
+        for _ in 0..100 {
+            backoff.reset();
+
+            let (_, delay) = backoff.next();
+            assert!(delay >= Duration::from_millis(375) && delay <= Duration::from_millis(625));
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use crate::backoff::Reconnect;
+use crate::client::Error;
+use crate::handle::Client;
+use crate::tls::{self, Trust};
+
+use rkvm_net::clipboard::{self, Clipboard, ClipboardBackend};
+use std::path::PathBuf;
+use thiserror::Error;
+use tokio_rustls::rustls::ServerName;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("No way of verifying the server certificate configured")]
+    NoTrust,
+    #[error("Neither a password nor a client certificate is configured")]
+    NoAuth,
+    #[error("Reconnect delays must be finite and not negative")]
+    Reconnect,
+}
+
+pub struct Builder {
+    hostname: ServerName,
+    port: u16,
+    name: String,
+    trust: Option<Trust>,
+    client_certificate: Option<(PathBuf, PathBuf)>,
+    password: Option<String>,
+    reconnect: Reconnect,
+    clipboard: Option<Box<dyn ClipboardBackend>>,
+}
+
+impl Builder {
+    pub(crate) fn new(hostname: ServerName, port: u16, name: String) -> Self {
+        Self {
+            hostname,
+            port,
+            name,
+            trust: None,
+            client_certificate: None,
+            password: None,
+            reconnect: Reconnect::default(),
+            clipboard: None,
+        }
+    }
+
+    pub fn trust(mut self, trust: Trust) -> Self {
+        self.trust = Some(trust);
+        self
+    }
+
+    pub fn client_certificate(mut self, certificate: PathBuf, key: PathBuf) -> Self {
+        self.client_certificate = Some((certificate, key));
+        self
+    }
+
+    // Without a password, a client certificate has to be provided.
+    pub fn password(mut self, password: String) -> Self {
+        self.password = Some(password);
+        self
+    }
+
+    pub fn reconnect(mut self, reconnect: Reconnect) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    pub fn clipboard(mut self, backend: Box<dyn ClipboardBackend>) -> Self {
+        self.clipboard = Some(backend);
+        self
+    }
+
+    // Validates the configuration and starts connecting to the server.
+    pub async fn build(self) -> Result<Client, Error> {
+        let trust = self.trust.as_ref().ok_or(ConfigError::NoTrust)?;
+
+        if self.password.is_none() && self.client_certificate.is_none() {
+            return Err(ConfigError::NoAuth.into());
+        }
+
+        if !self.reconnect.is_valid() {
+            return Err(ConfigError::Reconnect.into());
+        }
+
+        let client_certificate = self
+            .client_certificate
+            .as_ref()
+            .map(|(certificate, key)| (certificate.as_path(), key.as_path()));
+
+        let connector = tls::configure(trust, client_certificate)
+            .await
+            .map_err(Error::Tls)?;
+
+        let clipboard = match self.clipboard {
+            Some(backend) => Some(
+                Clipboard::new(backend, clipboard::POLL_INTERVAL)
+                    .await
+                    .map_err(Error::Clipboard)?,
+            ),
+            None => None,
+        };
+
+        Ok(Client::start(
+            self.hostname,
+            self.port,
+            self.name,
+            connector,
+            self.password,
+            self.reconnect,
+            clipboard,
+        ))
+    }
+}
@@ -1,18 +1,31 @@
+use crate::builder::ConfigError;
+use crate::tls;
+
+use futures::future;
+use rkvm_input::key::{Key, Keyboard};
+use rkvm_input::led::{Led, LedEvent};
 use rkvm_input::writer::Writer;
 use rkvm_net::auth::{AuthChallenge, AuthStatus};
+use rkvm_net::clipboard::Clipboard;
 use rkvm_net::message::Message;
-use rkvm_net::version::Version;
-use rkvm_net::{Pong, Update};
+use rkvm_net::recording::{self, Header, Record};
+use rkvm_net::version::{Capabilities, Handshake, Negotiated, Version};
+use rkvm_net::{Hello, Pong, Report, Update};
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io;
-use std::time::Instant;
+use std::path::Path;
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tokio::io::{AsyncWriteExt, BufStream};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufStream};
 use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch};
 use tokio::time;
+use tokio_rustls::client::TlsStream;
 use tokio_rustls::rustls::ServerName;
-use tokio_rustls::TlsConnector;
+
+pub type Stream = BufStream<TlsStream<TcpStream>>;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -20,18 +33,46 @@ pub enum Error {
     Network(io::Error),
     #[error("Input error: {0}")]
     Input(io::Error),
-    #[error("Incompatible server version (got {server}, expected {client})")]
-    Version { server: Version, client: Version },
+    #[error("Incompatible server version (server supports {server}, client supports {client})")]
+    Version {
+        server: Handshake,
+        client: Handshake,
+    },
     #[error("Invalid password")]
     Auth,
+    #[error("Recording error: {0}")]
+    Recording(io::Error),
+    #[error("Invalid replay speed {0}")]
+    Speed(f64),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("Error configuring TLS: {0}")]
+    Tls(tls::Error),
+    #[error(transparent)]
+    FingerprintMismatch(tls::Mismatch),
+    #[error("Error reading clipboard: {0}")]
+    Clipboard(io::Error),
+    #[error("Client stopped")]
+    Stopped,
 }
 
-pub async fn run(
+impl Error {
+    // Whether retrying the connection can not possibly help.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            Self::Version { .. } | Self::Auth | Self::FingerprintMismatch(_)
+        )
+    }
+}
+
+pub async fn connect(
     hostname: &ServerName,
     port: u16,
-    connector: TlsConnector,
-    password: &str,
-) -> Result<(), Error> {
+    connector: &tls::Connector,
+    password: Option<&str>,
+    name: &str,
+) -> Result<(Stream, Negotiated), Error> {
     // Intentionally don't impose any timeout for TCP connect.
     let stream = match hostname {
         ServerName::DnsName(name) => TcpStream::connect(&(name.as_ref(), port)).await,
@@ -47,14 +88,20 @@ pub async fn run(
         connector.connect(hostname.clone(), stream),
     )
     .await
-    .map_err(Error::Network)?;
+    .map_err(|err| match tls::Mismatch::of(&err) {
+        Some(mismatch) => Error::FingerprintMismatch(mismatch),
+        None => Error::Network(err),
+    })?;
 
     tracing::info!("TLS connected");
 
     let mut stream = BufStream::with_capacity(1024, 1024, stream);
 
+    // Legacy servers send their version right away and only accept their own one,
+    // so the server always has to be upgraded first.
     rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
         Version::CURRENT.encode(&mut stream).await?;
+        Handshake::CURRENT.encode(&mut stream).await?;
         stream.flush().await?;
 
         Ok(())
@@ -66,18 +113,30 @@ pub async fn run(
         .await
         .map_err(Error::Network)?;
 
-    if version != Version::CURRENT {
+    if version < Version::NEGOTIATION {
         return Err(Error::Version {
-            server: Version::CURRENT,
-            client: version,
+            server: Handshake::exact(version),
+            client: Handshake::CURRENT,
         });
     }
 
+    let peer = rkvm_net::timeout(rkvm_net::READ_TIMEOUT, Handshake::decode(&mut stream))
+        .await
+        .map_err(Error::Network)?;
+
+    let negotiated = Handshake::CURRENT.negotiate(&peer).ok_or(Error::Version {
+        server: peer,
+        client: Handshake::CURRENT,
+    })?;
+
+    tracing::info!(version = %negotiated.version, "Negotiated protocol version");
+
     let challenge = rkvm_net::timeout(rkvm_net::READ_TIMEOUT, AuthChallenge::decode(&mut stream))
         .await
         .map_err(Error::Network)?;
 
-    let response = challenge.respond(password);
+    // Without a password, the server relies on the client certificate instead.
+    let response = challenge.respond(password.unwrap_or_default());
 
     rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
         response.encode(&mut stream).await?;
@@ -99,110 +158,328 @@ pub async fn run(
 
     tracing::info!("Authenticated successfully");
 
-    let mut start = Instant::now();
+    rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
+        Hello {
+            name: name.to_owned(),
+        }
+        .encode(&mut stream)
+        .await?;
+        stream.flush().await?;
+
+        Ok(())
+    })
+    .await
+    .map_err(Error::Network)?;
 
-    let mut interval = time::interval(rkvm_net::PING_INTERVAL + rkvm_net::READ_TIMEOUT);
+    Ok((stream, negotiated))
+}
+
+// Returns once the shutdown sender is dropped, after releasing everything that is held down.
+pub async fn run(
+    stream: Stream,
+    capabilities: Capabilities,
+    clipboard: &mut Option<Clipboard>,
+    shutdown: &mut watch::Receiver<()>,
+) -> Result<(), Error> {
     let mut writers = HashMap::new();
+    let result = handle(stream, &mut writers, capabilities, clipboard, shutdown).await;
 
-    // Interval ticks immediately after creation.
-    interval.tick().await;
-
-    loop {
-        let update = tokio::select! {
-            update = Update::decode(&mut stream) => update.map_err(Error::Network)?,
-            _ = interval.tick() => return Err(Error::Network(io::Error::new(io::ErrorKind::TimedOut, "Ping timed out"))),
-        };
-
-        match update {
-            Update::CreateDevice {
-                id,
-                name,
-                vendor,
-                product,
-                version,
-                rel,
-                abs,
-                keys,
-                delay,
-                period,
-            } => {
-                let entry = writers.entry(id);
-                if let Entry::Occupied(_) = entry {
-                    return Err(Error::Network(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Server created the same device twice",
-                    )));
-                }
+    release(&mut writers).await;
+    result
+}
 
-                let writer = async {
-                    Writer::builder()?
-                        .name(&name)
-                        .vendor(vendor)
-                        .product(product)
-                        .version(version)
-                        .rel(rel)?
-                        .abs(abs)?
-                        .key(keys)?
-                        .delay(delay)?
-                        .period(period)?
-                        .build()
-                        .await
-                }
-                .await
-                .map_err(Error::Input)?;
-
-                entry.or_insert(writer);
-
-                tracing::info!(
-                    id = %id,
-                    name = ?name,
-                    vendor = %vendor,
-                    product = %product,
-                    version = %version,
-                    "Created new device"
-                );
+// Feeds a recording made by the server into local devices, dividing all delays by the speed.
+pub async fn replay(path: &Path, speed: f64) -> Result<(), Error> {
+    if !speed.is_finite() || speed <= 0.0 {
+        return Err(Error::Speed(speed));
+    }
+
+    let mut writers = HashMap::new();
+    let result = async {
+        let file = File::open(path).await.map_err(Error::Recording)?;
+        let mut file = BufReader::new(file);
+
+        Header::decode(&mut file).await.map_err(Error::Recording)?;
+
+        let start = time::Instant::now();
+        while let Some(Record { elapsed, update }) =
+            recording::read(&mut file).await.map_err(Error::Recording)?
+        {
+            // Tiny speeds can stretch delays past what a Duration or an Instant can hold.
+            let deadline = Duration::try_from_secs_f64(elapsed.as_secs_f64() / speed)
+                .ok()
+                .and_then(|elapsed| start.checked_add(elapsed))
+                .ok_or(Error::Speed(speed))?;
+
+            time::sleep_until(deadline).await;
+            apply(&mut writers, update).await?;
+        }
+
+        Ok(())
+    }
+    .await;
+
+    release(&mut writers).await;
+    result
+}
+
+// Makes sure nothing stays pressed once the devices go away.
+async fn release(writers: &mut HashMap<usize, Writer>) {
+    for (id, writer) in writers {
+        if let Err(err) = writer.release().await {
+            tracing::warn!(id = %id, "Failed to release keys: {}", err);
+        }
+    }
+}
+
+async fn handle<T: AsyncRead + AsyncWrite + Send + Unpin>(
+    stream: T,
+    writers: &mut HashMap<usize, Writer>,
+    capabilities: Capabilities,
+    clipboard: &mut Option<Clipboard>,
+    shutdown: &mut watch::Receiver<()>,
+) -> Result<(), Error> {
+    let reports = capabilities.intersects(Capabilities::REPORTS);
+    let leds = capabilities.contains(Capabilities::LEDS);
+    let sharing = capabilities.contains(Capabilities::CLIPBOARD);
+
+    // Decoding updates is not cancel safe, so read them on the side.
+    let (mut reader, mut stream) = tokio::io::split(stream);
+    let (sender, mut receiver) = mpsc::channel(1);
+
+    let read = async {
+        loop {
+            let update = Update::decode(&mut reader).await.map_err(Error::Network)?;
+
+            if sender.send(update).await.is_err() {
+                return Ok(());
             }
-            Update::DestroyDevice { id } => {
-                if writers.remove(&id).is_none() {
-                    return Err(Error::Network(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Server destroyed a nonexistent device",
-                    )));
+        }
+    };
+
+    let handle = async {
+        let mut start = Instant::now();
+
+        let mut interval = time::interval(rkvm_net::PING_INTERVAL + rkvm_net::READ_TIMEOUT);
+
+        // Interval ticks immediately after creation.
+        interval.tick().await;
+
+        loop {
+            let update = tokio::select! {
+                update = receiver.recv() => match update {
+                    Some(update) => update,
+                    None => return Ok(()),
+                },
+                _ = interval.tick() => return Err(Error::Network(io::Error::new(io::ErrorKind::TimedOut, "Ping timed out"))),
+                // Nothing is ever sent, the sender is only dropped.
+                _ = shutdown.changed() => return Ok(()),
+                (id, result) = read_led(writers), if leds => {
+                    let event = result.map_err(Error::Input)?;
+                    tracing::debug!(id = %id, led = ?event.led, on = %event.on, "Reporting LED change");
+
+                    rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
+                        Report::Led(event).encode(&mut stream).await?;
+                        stream.flush().await?;
+
+                        Ok(())
+                    })
+                    .await
+                    .map_err(Error::Network)?;
+
+                    continue;
                 }
+                result = read_clipboard(clipboard), if sharing => {
+                    let text = match result {
+                        Ok(text) => text,
+                        Err(err) => {
+                            tracing::warn!("Disabling clipboard sharing: {}", err);
+                            *clipboard = None;
 
-                tracing::info!(id = %id, "Destroyed device");
+                            continue;
+                        }
+                    };
+
+                    tracing::debug!(len = %text.len(), "Reporting clipboard change");
+
+                    rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
+                        Report::Clipboard(text).encode(&mut stream).await?;
+                        stream.flush().await?;
+
+                        Ok(())
+                    })
+                    .await
+                    .map_err(Error::Network)?;
+
+                    continue;
+                }
+            };
+
+            match update {
+                Update::Ping => {
+                    let duration = start.elapsed();
+                    tracing::debug!(duration = ?duration, "Received ping");
+
+                    start = Instant::now();
+                    interval.reset();
+
+                    rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
+                        match reports {
+                            true => Report::Pong.encode(&mut stream).await?,
+                            false => Pong.encode(&mut stream).await?,
+                        }
+
+                        stream.flush().await?;
+
+                        Ok(())
+                    })
+                    .await
+                    .map_err(Error::Network)?;
+
+                    let duration = start.elapsed();
+                    tracing::debug!(duration = ?duration, "Sent pong");
+                }
+                Update::Clipboard(text) => {
+                    // Failures are reported by the clipboard as changes.
+                    if let Some(clipboard) = clipboard {
+                        clipboard.set(text);
+                        tracing::debug!("Updating clipboard");
+                    }
+                }
+                update => apply(writers, update).await?,
             }
-            Update::Event { id, event } => {
-                let writer = writers.get_mut(&id).ok_or_else(|| {
-                    Error::Network(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Server sent an event to a nonexistent device",
-                    ))
-                })?;
+        }
+    };
 
-                writer.write(&event).await.map_err(Error::Input)?;
+    tokio::select! {
+        result = read => result,
+        result = handle => result,
+    }
+}
 
-                tracing::trace!(id = %id, "Wrote an event to device");
+// Applies an update concerning devices, ignoring any other.
+async fn apply(writers: &mut HashMap<usize, Writer>, update: Update) -> Result<(), Error> {
+    match update {
+        Update::CreateDevice {
+            id,
+            name,
+            vendor,
+            product,
+            version,
+            rel,
+            abs,
+            keys,
+            delay,
+            period,
+        } => {
+            let entry = writers.entry(id);
+            if let Entry::Occupied(_) = entry {
+                return Err(Error::Network(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Server created the same device twice",
+                )));
             }
-            Update::Ping => {
-                let duration = start.elapsed();
-                tracing::debug!(duration = ?duration, "Received ping");
 
-                start = Instant::now();
-                interval.reset();
+            let leds = device_leds(&keys);
+            let writer = async {
+                Writer::builder()?
+                    .name(&name)
+                    .vendor(vendor)
+                    .product(product)
+                    .version(version)
+                    .rel(rel)?
+                    .abs(abs)?
+                    .key(keys)?
+                    .led(leds)?
+                    .delay(delay)?
+                    .period(period)?
+                    .build()
+                    .await
+            }
+            .await
+            .map_err(Error::Input)?;
+
+            entry.or_insert(writer);
+
+            tracing::info!(
+                id = %id,
+                name = ?name,
+                vendor = %vendor,
+                product = %product,
+                version = %version,
+                "Created new device"
+            );
+        }
+        Update::DestroyDevice { id } => {
+            let mut writer = writers.remove(&id).ok_or_else(|| {
+                Error::Network(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Server destroyed a nonexistent device",
+                ))
+            })?;
 
-                rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
-                    Pong.encode(&mut stream).await?;
-                    stream.flush().await?;
+            writer.release().await.map_err(Error::Input)?;
 
-                    Ok(())
-                })
-                .await
-                .map_err(Error::Network)?;
+            tracing::info!(id = %id, "Destroyed device");
+        }
+        Update::Event { id, event } => {
+            let writer = writer(writers, id)?;
+            writer.write(&event).await.map_err(Error::Input)?;
 
-                let duration = start.elapsed();
-                tracing::debug!(duration = ?duration, "Sent pong");
+            tracing::trace!(id = %id, "Wrote an event to device");
+        }
+        Update::Frame { id, events } => {
+            let writer = writer(writers, id)?;
+            for event in &events {
+                writer.write(event).await.map_err(Error::Input)?;
             }
+
+            tracing::trace!(id = %id, len = %events.len(), "Wrote a frame to device");
         }
+        Update::Ping | Update::Clipboard(_) => {}
+    }
+
+    Ok(())
+}
+
+async fn read_clipboard(clipboard: &mut Option<Clipboard>) -> Result<String, io::Error> {
+    match clipboard {
+        Some(clipboard) => clipboard.changed().await,
+        None => future::pending().await,
     }
 }
+
+// Reads the next LED change requested on any of the devices.
+async fn read_led(writers: &mut HashMap<usize, Writer>) -> (usize, Result<LedEvent, io::Error>) {
+    if writers.is_empty() {
+        return future::pending().await;
+    }
+
+    let reads = writers
+        .iter_mut()
+        .map(|(id, writer)| Box::pin(async move { (*id, writer.read_led().await) }));
+
+    future::select_all(reads).await.0
+}
+
+// The protocol doesn't describe LEDs of devices, so give devices the LEDs of the lock keys they have.
+fn device_leds(keys: &HashSet<Key>) -> Vec<Led> {
+    [
+        (Keyboard::NumLock, Led::NumLock),
+        (Keyboard::CapsLock, Led::CapsLock),
+        (Keyboard::ScrollLock, Led::ScrollLock),
+    ]
+    .into_iter()
+    .filter(|(key, _)| keys.contains(&Key::Key(*key)))
+    .map(|(_, led)| led)
+    .collect()
+}
+
+fn writer(writers: &mut HashMap<usize, Writer>, id: usize) -> Result<&mut Writer, Error> {
+    writers.get_mut(&id).ok_or_else(|| {
+        Error::Network(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Server sent an event to a nonexistent device",
+        ))
+    })
+}
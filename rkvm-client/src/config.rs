@@ -1,3 +1,5 @@
+use rkvm_client::Fingerprint;
+use rkvm_net::clipboard::{ClipboardBackend, CommandBackend, FileBackend};
 use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer};
 use std::fmt::{self, Formatter};
@@ -10,8 +12,68 @@ use tokio_rustls::rustls::ServerName;
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     pub server: Server,
-    pub certificate: PathBuf,
-    pub password: String,
+    pub name: String,
+    pub certificate: Option<PathBuf>,
+    pub server_fingerprint: Option<Fingerprint>,
+    pub known_servers: Option<PathBuf>,
+    pub password: Option<String>,
+    pub client_certificate: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    #[serde(default)]
+    pub reconnect: Reconnect,
+    pub clipboard: Option<Clipboard>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Clipboard {
+    Wayland,
+    X11,
+    File(PathBuf),
+}
+
+impl From<Clipboard> for Box<dyn ClipboardBackend> {
+    fn from(clipboard: Clipboard) -> Self {
+        match clipboard {
+            Clipboard::Wayland => Box::new(CommandBackend::wayland()),
+            Clipboard::X11 => Box::new(CommandBackend::x11()),
+            Clipboard::File(path) => Box::new(FileBackend::new(path)),
+        }
+    }
+}
+
+// All delays are in seconds.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Reconnect {
+    pub initial_delay: f64,
+    pub max_delay: f64,
+    pub multiplier: f64,
+    pub jitter: f64,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        let default = rkvm_client::Reconnect::default();
+
+        Self {
+            initial_delay: default.initial_delay,
+            max_delay: default.max_delay,
+            multiplier: default.multiplier,
+            jitter: default.jitter,
+        }
+    }
+}
+
+impl From<Reconnect> for rkvm_client::Reconnect {
+    fn from(reconnect: Reconnect) -> Self {
+        Self {
+            initial_delay: reconnect.initial_delay,
+            max_delay: reconnect.max_delay,
+            multiplier: reconnect.multiplier,
+            jitter: reconnect.jitter,
+        }
+    }
 }
 
 pub struct Server {
@@ -120,6 +182,15 @@ mod tests {
         assert_eq!(parsed_ip, Ipv6Addr::from_str("::1").unwrap());
     }
 
+    #[test]
+    fn reconnect_parses() {
+        let parsed = toml::from_str::<Reconnect>("initial-delay = 1\nmax-delay = 10.5").unwrap();
+
+        assert_eq!(parsed.initial_delay, 1.0);
+        assert_eq!(parsed.max_delay, 10.5);
+        assert_eq!(parsed.multiplier, Reconnect::default().multiplier);
+    }
+
     #[test]
     fn example_parses() {
         let config = include_str!("../../example/client.toml");
@@ -0,0 +1,117 @@
+use crate::backoff::{Backoff, Reconnect};
+use crate::builder::Builder;
+use crate::client::{self, Error};
+use crate::tls::Connector;
+
+use rkvm_net::clipboard::Clipboard;
+use std::panic;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio_rustls::rustls::ServerName;
+
+// A client connecting to the server over and over, dropping it stops the client without waiting for it.
+pub struct Client {
+    // Dropped to stop the client.
+    shutdown: Option<watch::Sender<()>>,
+    task: Option<JoinHandle<Result<(), Error>>>,
+}
+
+impl Client {
+    // The name identifies the client on the server, unless it authenticates using a certificate.
+    pub fn builder(hostname: ServerName, port: u16, name: String) -> Builder {
+        Builder::new(hostname, port, name)
+    }
+
+    pub(crate) fn start(
+        hostname: ServerName,
+        port: u16,
+        name: String,
+        connector: Connector,
+        password: Option<String>,
+        reconnect: Reconnect,
+        mut clipboard: Option<Clipboard>,
+    ) -> Self {
+        let (shutdown_sender, mut shutdown) = watch::channel(());
+
+        let task = tokio::spawn(async move {
+            let mut backoff = Backoff::new(&reconnect);
+
+            loop {
+                // Connecting is simply abandoned on shutdown, as no devices exist yet.
+                let result = tokio::select! {
+                    result = client::connect(&hostname, port, &connector, password.as_deref(), &name) => result,
+                    _ = shutdown.changed() => return Ok(()),
+                };
+
+                // Once connected, the client releases everything held down before destroying its devices.
+                let result = match result {
+                    Ok((stream, negotiated)) => {
+                        backoff.reset();
+                        client::run(
+                            stream,
+                            negotiated.capabilities,
+                            &mut clipboard,
+                            &mut shutdown,
+                        )
+                        .await
+                    }
+                    Err(err) => Err(err),
+                };
+
+                if shutdown.has_changed().is_err() {
+                    return result;
+                }
+
+                match result {
+                    Ok(()) => tracing::info!("Disconnected"),
+                    Err(err) if err.is_fatal() => return Err(err),
+                    Err(err) => tracing::error!("Error: {}", err),
+                }
+
+                let (attempt, delay) = backoff.next();
+                tracing::info!(attempt = %attempt, delay = ?delay, "Reconnecting");
+
+                tokio::select! {
+                    _ = time::sleep(delay) => {}
+                    _ = shutdown.changed() => return Ok(()),
+                }
+            }
+        });
+
+        Self {
+            shutdown: Some(shutdown_sender),
+            task: Some(task),
+        }
+    }
+
+    // Waits until the client stops on its own, which only happens on errors retrying can't help with.
+    // This function is cancel safe.
+    pub async fn wait(&mut self) -> Result<(), Error> {
+        let result = match &mut self.task {
+            Some(task) => join(task).await,
+            None => return Err(Error::Stopped),
+        };
+
+        self.task = None;
+        result
+    }
+
+    // Disconnects from the server, returning once all devices have been destroyed.
+    pub async fn shutdown(mut self) -> Result<(), Error> {
+        drop(self.shutdown.take());
+
+        match &mut self.task {
+            Some(task) => join(task).await,
+            None => Ok(()),
+        }
+    }
+}
+
+async fn join(task: &mut JoinHandle<Result<(), Error>>) -> Result<(), Error> {
+    match task.await {
+        Ok(result) => result,
+        Err(err) if err.is_panic() => panic::resume_unwind(err.into_panic()),
+        Err(_) => Err(Error::Stopped),
+    }
+}
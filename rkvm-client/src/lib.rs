@@ -0,0 +1,11 @@
+mod backoff;
+mod builder;
+mod client;
+mod handle;
+mod tls;
+
+pub use backoff::Reconnect;
+pub use builder::{Builder, ConfigError};
+pub use client::{replay, Error};
+pub use handle::Client;
+pub use tls::{Fingerprint, Mismatch, Trust};
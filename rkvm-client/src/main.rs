@@ -1,10 +1,9 @@
-mod client;
 mod config;
-mod tls;
 
 use clap::Parser;
 use config::Config;
-use std::path::PathBuf;
+use rkvm_client::{Client, Trust};
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 use tokio::{fs, signal};
 use tracing::subscriber;
@@ -15,8 +14,18 @@ use tracing_subscriber::prelude::*;
 #[derive(Parser)]
 #[structopt(name = "rkvm-client", about = "The rkvm client application")]
 struct Args {
-    #[clap(help = "Path to configuration file")]
-    config_path: PathBuf,
+    #[clap(
+        help = "Path to configuration file",
+        required_unless_present = "replay"
+    )]
+    config_path: Option<PathBuf>,
+    #[clap(
+        help = "Replay a recording made by rkvm-server instead of connecting",
+        long
+    )]
+    replay: Option<PathBuf>,
+    #[clap(help = "Speed multiplier of the replay", long, default_value_t = 1.0)]
+    speed: f64,
 }
 
 #[tokio::main]
@@ -32,7 +41,13 @@ async fn main() -> ExitCode {
     subscriber::set_global_default(registry).unwrap();
 
     let args = Args::parse();
-    let config = match fs::read_to_string(&args.config_path).await {
+    let config_path = match (args.replay, args.config_path) {
+        (Some(path), _) => return replay(&path, args.speed).await,
+        (None, Some(config_path)) => config_path,
+        (None, None) => unreachable!("Config path is required unless replaying"),
+    };
+
+    let config = match fs::read_to_string(&config_path).await {
         Ok(config) => config,
         Err(err) => {
             tracing::error!("Error reading config: {}", err);
@@ -48,21 +63,87 @@ async fn main() -> ExitCode {
         }
     };
 
-    let connector = match tls::configure(&config.certificate).await {
-        Ok(connector) => connector,
+    let trust = match (
+        config.certificate,
+        config.server_fingerprint,
+        config.known_servers,
+    ) {
+        (Some(certificate), None, None) => Trust::Certificate(certificate),
+        (None, Some(fingerprint), None) => Trust::Fingerprint(fingerprint),
+        (None, None, Some(known_servers)) => Trust::FirstUse(known_servers),
+        _ => {
+            tracing::error!(
+                "Exactly one of certificate, server fingerprint or known servers file must be configured"
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut builder = Client::builder(config.server.hostname, config.server.port, config.name)
+        .trust(trust)
+        .reconnect(config.reconnect.into());
+
+    match (config.client_certificate, config.client_key) {
+        (Some(certificate), Some(key)) => builder = builder.client_certificate(certificate, key),
+        (None, None) => {}
+        _ => {
+            tracing::error!("Client certificate and key must be provided together");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if let Some(password) = config.password {
+        builder = builder.password(password);
+    }
+
+    if let Some(backend) = config.clipboard {
+        builder = builder.clipboard(backend.into());
+    }
+
+    let mut client = match builder.build().await {
+        Ok(client) => client,
         Err(err) => {
-            tracing::error!("Error configuring TLS: {}", err);
+            tracing::error!("Error: {}", err);
             return ExitCode::FAILURE;
         }
     };
 
     tokio::select! {
-        result = client::run(&config.server.hostname, config.server.port, connector, &config.password) => {
+        result = client.wait() => {
             if let Err(err) = result {
                 tracing::error!("Error: {}", err);
                 return ExitCode::FAILURE;
             }
         }
+        result = signal::ctrl_c() => {
+            if let Err(err) = result {
+                tracing::error!("Error setting up signal handler: {}", err);
+                return ExitCode::FAILURE;
+            }
+
+            tracing::info!("Exiting on signal");
+        }
+    }
+
+    // This is needed to properly clean libevdev stuff up.
+    if let Err(err) = client.shutdown().await {
+        tracing::error!("Error: {}", err);
+        return ExitCode::FAILURE;
+    }
+
+    ExitCode::SUCCESS
+}
+
+async fn replay(path: &Path, speed: f64) -> ExitCode {
+    tokio::select! {
+        result = rkvm_client::replay(path, speed) => {
+            if let Err(err) = result {
+                tracing::error!("Error: {}", err);
+                return ExitCode::FAILURE;
+            }
+
+            tracing::info!("Replay finished");
+        }
         // This is needed to properly clean libevdev stuff up.
         result = signal::ctrl_c() => {
             if let Err(err) = result {
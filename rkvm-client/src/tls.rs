@@ -1,9 +1,21 @@
-use std::io;
-use std::path::Path;
-use std::sync::Arc;
+use rustls_pemfile::Item;
+use serde::{de, Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use thiserror::Error;
 use tokio::fs;
-use tokio_rustls::rustls::{self, Certificate, ClientConfig, RootCertStore};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use tokio_rustls::rustls::{
+    self, Certificate, CertificateError, ClientConfig, PrivateKey, RootCertStore, ServerName,
+};
 use tokio_rustls::TlsConnector;
 
 #[derive(Error, Debug)]
@@ -12,23 +24,417 @@ pub enum Error {
     Rustls(#[from] rustls::Error),
     #[error(transparent)]
     Io(#[from] io::Error),
+    #[error("Multiple private keys provided")]
+    MultipleKeys,
+    #[error("No suitable private keys provided")]
+    NoKeys,
+    #[error("Invalid entry on line {0} of the known servers file")]
+    KnownServers(usize),
 }
 
-pub async fn configure(certificate: &Path) -> Result<TlsConnector, Error> {
-    let certificate = fs::read(certificate).await?;
-    let certificates = rustls_pemfile::certs(&mut certificate.as_slice())?;
+// The server offered a certificate other than the one it is pinned to, which retrying won't change.
+#[derive(Error, Clone, Debug)]
+pub enum Mismatch {
+    #[error("Server certificate fingerprint {offered} does not match the configured {expected}")]
+    Configured {
+        expected: Fingerprint,
+        offered: Fingerprint,
+    },
+    #[error(
+        "Certificate of {name} changed from {expected} to {offered}, remove it from {} if this is expected",
+        path.display()
+    )]
+    Known {
+        name: String,
+        expected: Fingerprint,
+        offered: Fingerprint,
+        path: PathBuf,
+    },
+}
+
+impl Mismatch {
+    // Digs the mismatch out of the error a failed handshake is reported with.
+    pub(crate) fn of(err: &io::Error) -> Option<Self> {
+        match err.get_ref()?.downcast_ref::<rustls::Error>()? {
+            rustls::Error::InvalidCertificate(CertificateError::Other(err)) => {
+                err.downcast_ref::<Self>().cloned()
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<Mismatch> for rustls::Error {
+    fn from(mismatch: Mismatch) -> Self {
+        Self::InvalidCertificate(CertificateError::Other(Arc::new(mismatch)))
+    }
+}
+
+// How the certificate of the server is verified.
+pub enum Trust {
+    // The certificate has to be issued by (or be one of) the certificates in this file.
+    Certificate(PathBuf),
+    // Only the certificate with this fingerprint is accepted.
+    Fingerprint(Fingerprint),
+    // The fingerprint of the certificate first seen for a server is recorded in this file
+    // and only that certificate is accepted from then on.
+    FirstUse(PathBuf),
+}
+
+// Connects to the server, saving the fingerprints of servers trusted on first use once the handshake succeeds.
+#[derive(Clone)]
+pub struct Connector {
+    connector: TlsConnector,
+    first_use: Option<Arc<FirstUseVerifier>>,
+}
+
+impl Connector {
+    pub async fn connect(
+        &self,
+        hostname: ServerName,
+        stream: TcpStream,
+    ) -> Result<TlsStream<TcpStream>, io::Error> {
+        let stream = self.connector.connect(hostname, stream).await?;
+
+        if let Some(verifier) = &self.first_use {
+            verifier.save().await.map_err(|err| {
+                io::Error::new(err.kind(), format!("Error saving known servers: {}", err))
+            })?;
+        }
+
+        Ok(stream)
+    }
+}
+
+// The client certificate is given along with its private key.
+pub async fn configure(
+    trust: &Trust,
+    client_certificate: Option<(&Path, &Path)>,
+) -> Result<Connector, Error> {
+    let mut first_use = None;
+
+    let builder = ClientConfig::builder().with_safe_defaults();
+    let builder = match trust {
+        Trust::Certificate(path) => {
+            let certificate = fs::read(path).await?;
+            let certificates = rustls_pemfile::certs(&mut certificate.as_slice())?;
+
+            let mut store = RootCertStore::empty();
+            for certificate in certificates {
+                store.add(&Certificate(certificate))?;
+            }
+
+            builder.with_custom_certificate_verifier(Arc::new(WebPkiVerifier::new(store, None)))
+        }
+        Trust::Fingerprint(fingerprint) => {
+            builder.with_custom_certificate_verifier(Arc::new(FingerprintVerifier(*fingerprint)))
+        }
+        Trust::FirstUse(path) => {
+            let known = match fs::read_to_string(path).await {
+                Ok(data) => parse_known(&data)?,
+                Err(err) if err.kind() == ErrorKind::NotFound => BTreeMap::new(),
+                Err(err) => return Err(err.into()),
+            };
+
+            let verifier = Arc::new(FirstUseVerifier {
+                path: path.to_owned(),
+                known: Mutex::new(known),
+                new: Mutex::new(None),
+            });
+
+            first_use = Some(verifier.clone());
+            builder.with_custom_certificate_verifier(verifier)
+        }
+    };
+
+    let config = match client_certificate {
+        Some((certificate, key)) => {
+            let certificate = fs::read(certificate).await?;
+            let certificates = rustls_pemfile::certs(&mut certificate.as_slice())?
+                .into_iter()
+                .map(Certificate)
+                .collect();
+
+            let key = fs::read(key).await?;
+            let mut keys = rustls_pemfile::read_all(&mut key.as_slice())?
+                .into_iter()
+                .filter_map(|item| match item {
+                    Item::RSAKey(data) | Item::PKCS8Key(data) | Item::ECKey(data) => {
+                        Some(PrivateKey(data))
+                    }
+                    _ => None,
+                });
+
+            let key = keys.next().ok_or(Error::NoKeys)?;
+            if keys.next().is_some() {
+                return Err(Error::MultipleKeys);
+            }
+
+            builder.with_client_auth_cert(certificates, key)?
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(Connector {
+        connector: Arc::new(config).into(),
+        first_use,
+    })
+}
+
+// SHA-256 hash of a DER encoded certificate.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    pub fn of(certificate: &Certificate) -> Self {
+        Self(Sha256::digest(&certificate.0).into())
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "sha256:")?;
+
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Invalid fingerprint (expected sha256:<hex digits>)")]
+pub struct InvalidFingerprint;
+
+impl FromStr for Fingerprint {
+    type Err = InvalidFingerprint;
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        let data = data.strip_prefix("sha256:").ok_or(InvalidFingerprint)?;
+
+        // Allow the colon separated form printed by OpenSSL.
+        let digits = data
+            .chars()
+            .filter(|c| *c != ':')
+            .map(|c| c.to_digit(16).map(|digit| digit as u8))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(InvalidFingerprint)?;
+
+        if digits.len() != 64 {
+            return Err(InvalidFingerprint);
+        }
+
+        let mut bytes = [0; 32];
+        for (byte, digits) in bytes.iter_mut().zip(digits.chunks(2)) {
+            *byte = digits[0] << 4 | digits[1];
+        }
+
+        Ok(Self(bytes))
+    }
+}
+
+impl<'de> Deserialize<'de> for Fingerprint {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+struct FingerprintVerifier(Fingerprint);
 
-    let mut store = RootCertStore::empty();
-    for certificate in certificates {
-        store.add(&Certificate(certificate))?;
+impl ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = Fingerprint::of(end_entity);
+        if fingerprint != self.0 {
+            return Err(Mismatch::Configured {
+                expected: self.0,
+                offered: fingerprint,
+            }
+            .into());
+        }
+
+        Ok(ServerCertVerified::assertion())
     }
+}
+
+struct FirstUseVerifier {
+    path: PathBuf,
+    // Fingerprints of known servers by their hostname.
+    known: Mutex<BTreeMap<String, Fingerprint>>,
+    // Server seen for the first time during the ongoing handshake, it's only known once saved.
+    new: Mutex<Option<(String, Fingerprint)>>,
+}
+
+impl FirstUseVerifier {
+    async fn save(&self) -> Result<(), io::Error> {
+        let (name, fingerprint) = match self.new.lock().unwrap().take() {
+            Some(new) => new,
+            None => return Ok(()),
+        };
+
+        let mut known = self.known.lock().unwrap().clone();
+        known.insert(name.clone(), fingerprint);
+
+        fs::write(&self.path, format_known(&known)).await?;
+        *self.known.lock().unwrap() = known;
+
+        tracing::info!(%fingerprint, "Trusting certificate of {} from now on", name);
+        Ok(())
+    }
+}
+
+impl ServerCertVerifier for FirstUseVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let name = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_owned(),
+            ServerName::IpAddress(address) => address.to_string(),
+            _ => unimplemented!("Unhandled rustls ServerName variant: {:?}", server_name),
+        };
+
+        let fingerprint = Fingerprint::of(end_entity);
+        let known = self.known.lock().unwrap();
+
+        match known.get(&name) {
+            Some(expected) if *expected == fingerprint => {}
+            Some(expected) => {
+                return Err(Mismatch::Known {
+                    name,
+                    expected: *expected,
+                    offered: fingerprint,
+                    path: self.path.clone(),
+                }
+                .into());
+            }
+            // Saving the fingerprint is left to the connector, as writing files would block the runtime.
+            None => *self.new.lock().unwrap() = Some((name, fingerprint)),
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+// Each line contains a hostname followed by the fingerprint of its certificate.
+fn parse_known(data: &str) -> Result<BTreeMap<String, Fingerprint>, Error> {
+    let mut known = BTreeMap::new();
 
-    let config = Arc::new(
-        ClientConfig::builder()
-            .with_safe_defaults()
-            .with_root_certificates(store)
-            .with_no_client_auth(),
-    );
+    for (i, line) in data.lines().enumerate() {
+        let mut parts = line.split_whitespace();
 
-    Ok(config.into())
+        let (name, fingerprint) = match (parts.next(), parts.next(), parts.next()) {
+            (None, _, _) => continue,
+            (Some(name), Some(fingerprint), None) => (name, fingerprint),
+            _ => return Err(Error::KnownServers(i + 1)),
+        };
+
+        let fingerprint = fingerprint
+            .parse()
+            .map_err(|_| Error::KnownServers(i + 1))?;
+
+        known.insert(name.to_owned(), fingerprint);
+    }
+
+    Ok(known)
+}
+
+fn format_known(known: &BTreeMap<String, Fingerprint>) -> String {
+    known
+        .iter()
+        .map(|(name, fingerprint)| format!("{} {}\n", name, fingerprint))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fingerprint_roundtrips() {
+        let fingerprint = Fingerprint::of(&Certificate(b"certificate".to_vec()));
+        let formatted = fingerprint.to_string();
+
+        assert!(formatted.starts_with("sha256:"));
+        assert_eq!(formatted.parse::<Fingerprint>().unwrap(), fingerprint);
+
+        let colons = formatted
+            .strip_prefix("sha256:")
+            .unwrap()
+            .to_uppercase()
+            .as_bytes()
+            .chunks(2)
+            .map(|chunk| std::str::from_utf8(chunk).unwrap())
+            .collect::<Vec<_>>()
+            .join(":");
+
+        assert_eq!(
+            format!("sha256:{}", colons).parse::<Fingerprint>().unwrap(),
+            fingerprint
+        );
+    }
+
+    #[test]
+    fn fingerprint_rejects_invalid() {
+        assert!("sha256:abcd".parse::<Fingerprint>().is_err());
+        assert!("md5:00".parse::<Fingerprint>().is_err());
+        assert!(format!("sha256:{}", "zz".repeat(32))
+            .parse::<Fingerprint>()
+            .is_err());
+    }
+
+    #[test]
+    fn known_servers_roundtrip() {
+        let fingerprint = Fingerprint::of(&Certificate(Vec::new()));
+        let known = BTreeMap::from([("myserver.local".to_owned(), fingerprint)]);
+
+        assert_eq!(parse_known(&format_known(&known)).unwrap(), known);
+        assert!(matches!(
+            parse_known("\nmyserver.local"),
+            Err(Error::KnownServers(2))
+        ));
+    }
+
+    #[test]
+    fn mismatch_survives_handshake_error() {
+        let verifier = FingerprintVerifier(Fingerprint::of(&Certificate(Vec::new())));
+        let err = verifier
+            .verify_server_cert(
+                &Certificate(b"certificate".to_vec()),
+                &[],
+                &ServerName::try_from("myserver.local").unwrap(),
+                &mut std::iter::empty(),
+                &[],
+                SystemTime::now(),
+            )
+            .unwrap_err();
+
+        // This is how the handshake error reaches the connector.
+        let err = io::Error::new(ErrorKind::InvalidData, err);
+        assert!(matches!(
+            Mismatch::of(&err),
+            Some(Mismatch::Configured { expected, offered })
+                if expected == verifier.0 && offered == Fingerprint::of(&Certificate(b"certificate".to_vec()))
+        ));
+
+        assert!(Mismatch::of(&io::Error::from(ErrorKind::ConnectionReset)).is_none());
+    }
 }
@@ -0,0 +1,114 @@
+use clap::{Parser, Subcommand};
+use rkvm_net::control::{Request, Response};
+use rkvm_net::message::Message;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use thiserror::Error;
+use tokio::io::{AsyncWriteExt, BufStream};
+use tokio::net::UnixStream;
+
+#[derive(Parser)]
+#[clap(name = "rkvmctl", about = "A tool to control a running rkvm server")]
+struct Args {
+    #[clap(
+        long,
+        short,
+        help = "Path to the server's control socket",
+        default_value = "/run/rkvm-server.sock"
+    )]
+    socket: PathBuf,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[clap(about = "List connected clients")]
+    Clients,
+    #[clap(about = "List devices grabbed by the server")]
+    Devices,
+    #[clap(about = "Show the currently focused target")]
+    Focus,
+    #[clap(about = "Focus a target, \"local\" being the server itself")]
+    Switch { target: String },
+    #[clap(about = "Disconnect a client")]
+    Disconnect { name: String },
+    #[clap(about = "Reload the server's configuration")]
+    Reload,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let request = match args.command {
+        Command::Clients => Request::Clients,
+        Command::Devices => Request::Devices,
+        Command::Focus => Request::Focus,
+        Command::Switch { target } => Request::Switch { target },
+        Command::Disconnect { name } => Request::Disconnect { name },
+        Command::Reload => Request::Reload,
+    };
+
+    let response = match run(&args.socket, request).await {
+        Ok(response) => response,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match response {
+        Response::Clients(clients) => {
+            for client in clients {
+                println!(
+                    "{}\t{}\t{}\t{}ms",
+                    client.name,
+                    client.addr,
+                    client.dropped,
+                    client.lag.as_millis()
+                );
+            }
+        }
+        Response::Devices(devices) => {
+            for device in devices {
+                println!(
+                    "{}\t{:04x}:{:04x}\t{}\t{}",
+                    device.id, device.vendor, device.product, device.dropped, device.name
+                );
+            }
+        }
+        Response::Focus(target) => println!("{}", target),
+        Response::Done => {}
+        Response::Error(err) => {
+            eprintln!("Error: {}", err);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+#[derive(Error, Debug)]
+enum Error {
+    #[error("Error connecting to {0:?}: {1}")]
+    Connect(PathBuf, io::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+async fn run(socket: &Path, request: Request) -> Result<Response, Error> {
+    let stream = UnixStream::connect(socket)
+        .await
+        .map_err(|err| Error::Connect(socket.to_owned(), err))?;
+
+    let mut stream = BufStream::new(stream);
+
+    request.encode(&mut stream).await?;
+    stream.flush().await?;
+
+    let response = Response::decode(&mut stream).await?;
+
+    Ok(response)
+}
@@ -169,6 +169,14 @@ pub struct AbsInfo {
     pub resolution: i32,
 }
 
+impl AbsInfo {
+    // Some buggy kernels can report nonsense abs info, which uinput refuses to create a virtual device with.
+    // See Linux source at drivers/input/misc/uinput.c#L408 commit 93f5de5f648d2b1ce3540a4ac71756d4a852dc23.
+    pub fn is_nonsense(&self) -> bool {
+        (self.min != 0 || self.max != 0) && self.max < self.min
+    }
+}
+
 #[derive(Clone, Copy, Serialize, Deserialize, Debug)]
 pub enum ToolType {
     Finger,
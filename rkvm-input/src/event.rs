@@ -1,14 +1,16 @@
 use crate::abs::AbsEvent;
 use crate::key::KeyEvent;
+use crate::led::LedEvent;
 use crate::rel::RelEvent;
 use crate::sync::SyncEvent;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum Event {
     Rel(RelEvent),
     Abs(AbsEvent),
     Key(KeyEvent),
     Sync(SyncEvent),
+    Led(LedEvent),
 }
@@ -1,23 +1,22 @@
 use crate::abs::{AbsAxis, AbsInfo};
 use crate::convert::Convert;
+use crate::evdev::Evdev;
 use crate::glue;
-use crate::interceptor::Interceptor;
 use crate::key::Key;
 use crate::rel::RelAxis;
 
 pub struct RelCaps<'a> {
     current: u16,
-    interceptor: &'a Interceptor,
+    evdev: &'a Evdev,
 }
 
 impl<'a> RelCaps<'a> {
-    pub(super) fn new(interceptor: &'a Interceptor) -> Self {
-        let has =
-            unsafe { glue::libevdev_has_event_type(interceptor.evdev.as_ptr(), glue::EV_REL) == 1 };
+    pub(crate) fn new(evdev: &'a Evdev) -> Self {
+        let has = unsafe { glue::libevdev_has_event_type(evdev.as_ptr(), glue::EV_REL) == 1 };
 
         Self {
             current: if has { 0 } else { glue::REL_MAX as _ },
-            interceptor,
+            evdev,
         }
     }
 }
@@ -28,11 +27,8 @@ impl Iterator for RelCaps<'_> {
     fn next(&mut self) -> Option<Self::Item> {
         while self.current < glue::REL_MAX as _ {
             let has = unsafe {
-                glue::libevdev_has_event_code(
-                    self.interceptor.evdev.as_ptr(),
-                    glue::EV_REL,
-                    self.current as _,
-                ) == 1
+                glue::libevdev_has_event_code(self.evdev.as_ptr(), glue::EV_REL, self.current as _)
+                    == 1
             };
 
             self.current += 1;
@@ -52,17 +48,16 @@ impl Iterator for RelCaps<'_> {
 
 pub struct AbsCaps<'a> {
     current: u16,
-    interceptor: &'a Interceptor,
+    evdev: &'a Evdev,
 }
 
 impl<'a> AbsCaps<'a> {
-    pub(super) fn new(interceptor: &'a Interceptor) -> Self {
-        let has =
-            unsafe { glue::libevdev_has_event_type(interceptor.evdev.as_ptr(), glue::EV_ABS) == 1 };
+    pub(crate) fn new(evdev: &'a Evdev) -> Self {
+        let has = unsafe { glue::libevdev_has_event_type(evdev.as_ptr(), glue::EV_ABS) == 1 };
 
         Self {
             current: if has { 0 } else { glue::ABS_MAX as _ },
-            interceptor,
+            evdev,
         }
     }
 }
@@ -73,11 +68,8 @@ impl Iterator for AbsCaps<'_> {
     fn next(&mut self) -> Option<Self::Item> {
         while self.current < glue::ABS_MAX as _ {
             let has = unsafe {
-                glue::libevdev_has_event_code(
-                    self.interceptor.evdev.as_ptr(),
-                    glue::EV_ABS,
-                    self.current as _,
-                ) == 1
+                glue::libevdev_has_event_code(self.evdev.as_ptr(), glue::EV_ABS, self.current as _)
+                    == 1
             };
 
             self.current += 1;
@@ -88,10 +80,7 @@ impl Iterator for AbsCaps<'_> {
 
             if let Some(axis) = AbsAxis::from_raw(self.current - 1) {
                 let info = unsafe {
-                    glue::libevdev_get_abs_info(
-                        self.interceptor.evdev.as_ptr(),
-                        (self.current - 1) as _,
-                    )
+                    glue::libevdev_get_abs_info(self.evdev.as_ptr(), (self.current - 1) as _)
                 };
 
                 let info = unsafe { info.as_ref().unwrap() };
@@ -113,17 +102,16 @@ impl Iterator for AbsCaps<'_> {
 
 pub struct KeyCaps<'a> {
     current: u16,
-    interceptor: &'a Interceptor,
+    evdev: &'a Evdev,
 }
 
 impl<'a> KeyCaps<'a> {
-    pub(super) fn new(interceptor: &'a Interceptor) -> Self {
-        let has =
-            unsafe { glue::libevdev_has_event_type(interceptor.evdev.as_ptr(), glue::EV_KEY) == 1 };
+    pub(crate) fn new(evdev: &'a Evdev) -> Self {
+        let has = unsafe { glue::libevdev_has_event_type(evdev.as_ptr(), glue::EV_KEY) == 1 };
 
         Self {
             current: if has { 0 } else { glue::KEY_MAX as _ },
-            interceptor,
+            evdev,
         }
     }
 }
@@ -134,11 +122,8 @@ impl Iterator for KeyCaps<'_> {
     fn next(&mut self) -> Option<Self::Item> {
         while self.current < glue::KEY_MAX as _ {
             let has = unsafe {
-                glue::libevdev_has_event_code(
-                    self.interceptor.evdev.as_ptr(),
-                    glue::EV_KEY,
-                    self.current as _,
-                ) == 1
+                glue::libevdev_has_event_code(self.evdev.as_ptr(), glue::EV_KEY, self.current as _)
+                    == 1
             };
 
             self.current += 1;
@@ -162,39 +147,26 @@ pub struct Repeat {
 }
 
 impl Repeat {
-    pub(super) fn new(interceptor: &Interceptor) -> Self {
+    pub(crate) fn new(evdev: &Evdev) -> Self {
         let has = unsafe {
-            glue::libevdev_has_event_code(interceptor.evdev.as_ptr(), glue::EV_REP, glue::REP_DELAY)
-                == 1
+            glue::libevdev_has_event_code(evdev.as_ptr(), glue::EV_REP, glue::REP_DELAY) == 1
         };
 
         let delay = if has {
             Some(unsafe {
-                glue::libevdev_get_event_value(
-                    interceptor.evdev.as_ptr(),
-                    glue::EV_REP,
-                    glue::REP_DELAY,
-                )
+                glue::libevdev_get_event_value(evdev.as_ptr(), glue::EV_REP, glue::REP_DELAY)
             })
         } else {
             None
         };
 
         let has = unsafe {
-            glue::libevdev_has_event_code(
-                interceptor.evdev.as_ptr(),
-                glue::EV_REP,
-                glue::REP_PERIOD,
-            ) == 1
+            glue::libevdev_has_event_code(evdev.as_ptr(), glue::EV_REP, glue::REP_PERIOD) == 1
         };
 
         let period = if has {
             Some(unsafe {
-                glue::libevdev_get_event_value(
-                    interceptor.evdev.as_ptr(),
-                    glue::EV_REP,
-                    glue::REP_PERIOD,
-                )
+                glue::libevdev_get_event_value(evdev.as_ptr(), glue::EV_REP, glue::REP_PERIOD)
             })
         } else {
             None
@@ -0,0 +1,96 @@
+use super::{check, decode, grab, Interceptor, OpenError, Properties, Rejection, Resync};
+use crate::evdev::Evdev;
+use crate::event::Event;
+use crate::glue;
+
+use std::io::Error;
+use std::path::Path;
+
+// Read-only access to a device for diagnostic purposes, without creating a virtual device for it.
+pub struct Inspector {
+    evdev: Evdev,
+    grabbed: bool,
+    resync: Resync,
+}
+
+impl Inspector {
+    pub async fn open(path: &Path) -> Result<Self, Error> {
+        let evdev = Evdev::open(path).await?;
+
+        Ok(Self {
+            evdev,
+            grabbed: false,
+            resync: Resync::default(),
+        })
+    }
+
+    pub fn properties(&self) -> Properties<'_> {
+        Properties::new(&self.evdev)
+    }
+
+    // Tells why the device would not be intercepted, not taking any filters into account.
+    // Unless it's already grabbed, the device is grabbed for a moment to find out whether it's busy.
+    pub fn check(&self) -> Result<Option<Rejection>, Error> {
+        if let Err(rejection) = check(&self.evdev) {
+            return Ok(Some(rejection));
+        }
+
+        if self.grabbed {
+            return Ok(None);
+        }
+
+        match grab(&self.evdev) {
+            Ok(()) => {}
+            Err(OpenError::NotAppliable(rejection)) => return Ok(Some(rejection)),
+            Err(OpenError::Io(err)) => return Err(err),
+        }
+
+        let ret = unsafe {
+            glue::libevdev_grab(
+                self.evdev.as_ptr(),
+                glue::libevdev_grab_mode_LIBEVDEV_UNGRAB,
+            )
+        };
+
+        if ret < 0 {
+            return Err(Error::from_raw_os_error(-ret));
+        }
+
+        Ok(None)
+    }
+
+    // Keeps events of the device from reaching anyone else, until the inspector is dropped.
+    pub fn grab(&mut self) -> Result<(), Error> {
+        match grab(&self.evdev) {
+            Ok(()) => {}
+            Err(OpenError::NotAppliable(rejection)) => return Err(Error::other(rejection)),
+            Err(OpenError::Io(err)) => return Err(err),
+        }
+
+        self.grabbed = true;
+        Ok(())
+    }
+
+    // Returns events one at a time as they come, unlike interceptors also reporting LED changes.
+    pub async fn read(&mut self) -> Result<Event, Error> {
+        loop {
+            let raw = if self.resync.syncing() {
+                Interceptor::read_sync(&self.evdev)?
+            } else {
+                Some(Interceptor::read_raw(&self.evdev).await?)
+            };
+
+            let (r#type, code, value) = match self.resync.next(raw) {
+                Some(raw) => raw,
+                None => {
+                    self.resync.log();
+                    continue;
+                }
+            };
+
+            if let Some(event) = decode(r#type, code, value) {
+                return Ok(event);
+            }
+        }
+    }
+}
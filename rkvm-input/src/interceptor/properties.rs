@@ -0,0 +1,81 @@
+use super::caps::{AbsCaps, KeyCaps, RelCaps, Repeat};
+use crate::evdev::Evdev;
+use crate::glue;
+
+use std::ffi::CStr;
+
+// Types of events a device can produce.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Capability {
+    Rel,
+    Abs,
+    Key,
+    Led,
+}
+
+// Describes a device, available before it is grabbed.
+pub struct Properties<'a> {
+    evdev: &'a Evdev,
+}
+
+impl<'a> Properties<'a> {
+    pub(crate) fn new(evdev: &'a Evdev) -> Self {
+        Self { evdev }
+    }
+
+    pub fn name(&self) -> &'a CStr {
+        let name = unsafe { glue::libevdev_get_name(self.evdev.as_ptr()) };
+        let name = unsafe { CStr::from_ptr(name) };
+
+        name
+    }
+
+    pub fn vendor(&self) -> u16 {
+        unsafe { glue::libevdev_get_id_vendor(self.evdev.as_ptr()) as _ }
+    }
+
+    pub fn product(&self) -> u16 {
+        unsafe { glue::libevdev_get_id_product(self.evdev.as_ptr()) as _ }
+    }
+
+    pub fn version(&self) -> u16 {
+        unsafe { glue::libevdev_get_id_version(self.evdev.as_ptr()) as _ }
+    }
+
+    // Physical location of the device, such as the USB port it's plugged into.
+    pub fn phys(&self) -> Option<&'a CStr> {
+        let phys = unsafe { glue::libevdev_get_phys(self.evdev.as_ptr()) };
+        if phys.is_null() {
+            return None;
+        }
+
+        Some(unsafe { CStr::from_ptr(phys) })
+    }
+
+    pub fn has(&self, capability: Capability) -> bool {
+        let r#type = match capability {
+            Capability::Rel => glue::EV_REL,
+            Capability::Abs => glue::EV_ABS,
+            Capability::Key => glue::EV_KEY,
+            Capability::Led => glue::EV_LED,
+        };
+
+        unsafe { glue::libevdev_has_event_type(self.evdev.as_ptr(), r#type) == 1 }
+    }
+
+    pub fn rel(&self) -> RelCaps<'a> {
+        RelCaps::new(self.evdev)
+    }
+
+    pub fn abs(&self) -> AbsCaps<'a> {
+        AbsCaps::new(self.evdev)
+    }
+
+    pub fn key(&self) -> KeyCaps<'a> {
+        KeyCaps::new(self.evdev)
+    }
+
+    pub fn repeat(&self) -> Repeat {
+        Repeat::new(self.evdev)
+    }
+}
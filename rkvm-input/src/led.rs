@@ -0,0 +1,66 @@
+use crate::convert::Convert;
+use crate::glue;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct LedEvent {
+    pub led: Led,
+    pub on: bool,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Led {
+    NumLock,
+    CapsLock,
+    ScrollLock,
+    Compose,
+    Kana,
+    Sleep,
+    Suspend,
+    Mute,
+    Misc,
+    Mail,
+    Charging,
+}
+
+impl Convert for Led {
+    type Raw = u16;
+
+    fn from_raw(code: Self::Raw) -> Option<Self> {
+        let led = match code as _ {
+            glue::LED_NUML => Self::NumLock,
+            glue::LED_CAPSL => Self::CapsLock,
+            glue::LED_SCROLLL => Self::ScrollLock,
+            glue::LED_COMPOSE => Self::Compose,
+            glue::LED_KANA => Self::Kana,
+            glue::LED_SLEEP => Self::Sleep,
+            glue::LED_SUSPEND => Self::Suspend,
+            glue::LED_MUTE => Self::Mute,
+            glue::LED_MISC => Self::Misc,
+            glue::LED_MAIL => Self::Mail,
+            glue::LED_CHARGING => Self::Charging,
+            _ => return None,
+        };
+
+        Some(led)
+    }
+
+    fn to_raw(&self) -> Option<Self::Raw> {
+        let code = match self {
+            Self::NumLock => glue::LED_NUML,
+            Self::CapsLock => glue::LED_CAPSL,
+            Self::ScrollLock => glue::LED_SCROLLL,
+            Self::Compose => glue::LED_COMPOSE,
+            Self::Kana => glue::LED_KANA,
+            Self::Sleep => glue::LED_SLEEP,
+            Self::Suspend => glue::LED_SUSPEND,
+            Self::Mute => glue::LED_MUTE,
+            Self::Misc => glue::LED_MISC,
+            Self::Mail => glue::LED_MAIL,
+            Self::Charging => glue::LED_CHARGING,
+        };
+
+        Some(code as _)
+    }
+}
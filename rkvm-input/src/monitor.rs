@@ -1,4 +1,4 @@
-use crate::interceptor::{Interceptor, OpenError};
+use crate::interceptor::{Filter, Interceptor, OpenError, Properties};
 use crate::registry::Registry;
 
 use futures::StreamExt;
@@ -6,21 +6,32 @@ use inotify::{Inotify, WatchMask};
 use std::ffi::OsStr;
 use std::io::{Error, ErrorKind};
 use std::path::Path;
+use std::sync::Arc;
 use tokio::fs;
 use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::Notify;
 
 const EVENT_PATH: &str = "/dev/input";
 
 pub struct Monitor {
     receiver: Receiver<Result<Interceptor, Error>>,
+    rescan: Arc<Notify>,
 }
 
 impl Monitor {
-    pub fn new() -> Self {
+    // Only devices the filter returns true for are grabbed.
+    pub fn new<F: Fn(&Properties) -> bool + Send + Sync + 'static>(filter: F) -> Self {
         let (sender, receiver) = mpsc::channel(1);
-        tokio::spawn(monitor(sender));
+        let rescan = Arc::new(Notify::new());
+        tokio::spawn(monitor(sender, Box::new(filter), rescan.clone()));
 
-        Self { receiver }
+        Self { receiver, rescan }
+    }
+
+    // Goes through all present devices again, for example after the filter started accepting devices it previously
+    // rejected. Devices that are already grabbed are left alone.
+    pub fn rescan(&self) {
+        self.rescan.notify_one();
     }
 
     pub async fn read(&mut self) -> Result<Interceptor, Error> {
@@ -31,7 +42,11 @@ impl Monitor {
     }
 }
 
-async fn monitor(sender: Sender<Result<Interceptor, Error>>) {
+async fn monitor(
+    sender: Sender<Result<Interceptor, Error>>,
+    filter: Box<Filter>,
+    rescan: Arc<Notify>,
+) {
     let run = async {
         let registry = Registry::new();
 
@@ -47,17 +62,23 @@ async fn monitor(sender: Sender<Result<Interceptor, Error>>) {
         loop {
             let path = match read_dir.next_entry().await? {
                 Some(entry) => entry.path(),
-                None => match stream.next().await {
-                    Some(event) => {
-                        let event = event?;
-                        let name = match event.name {
-                            Some(name) => name,
-                            None => continue,
-                        };
-
-                        Path::new(EVENT_PATH).join(&name)
+                None => tokio::select! {
+                    event = stream.next() => match event {
+                        Some(event) => {
+                            let event = event?;
+                            let name = match event.name {
+                                Some(name) => name,
+                                None => continue,
+                            };
+
+                            Path::new(EVENT_PATH).join(&name)
+                        }
+                        None => break,
+                    },
+                    _ = rescan.notified() => {
+                        read_dir = fs::read_dir(EVENT_PATH).await?;
+                        continue;
                     }
-                    None => break,
                 },
             };
 
@@ -70,10 +91,13 @@ async fn monitor(sender: Sender<Result<Interceptor, Error>>) {
                 continue;
             }
 
-            let interceptor = match Interceptor::open(&path, &registry).await {
+            let interceptor = match Interceptor::open(&path, &registry, &filter).await {
                 Ok(interceptor) => interceptor,
                 Err(OpenError::Io(err)) => return Err(err),
-                Err(OpenError::NotAppliable) => continue,
+                Err(OpenError::NotAppliable(rejection)) => {
+                    tracing::debug!("Skipping {:?}: {}", path, rejection);
+                    continue;
+                }
             };
 
             if sender.send(Ok(interceptor)).await.is_err() {
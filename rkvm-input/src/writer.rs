@@ -4,19 +4,26 @@ use crate::abs::{AbsAxis, AbsEvent, AbsInfo};
 use crate::convert::Convert;
 use crate::evdev::Evdev;
 use crate::event::Event;
-use crate::glue::{self, input_absinfo};
+use crate::glue::{self, input_absinfo, input_event};
 use crate::key::{Key, KeyEvent};
+use crate::led::{Led, LedEvent};
 use crate::rel::{RelAxis, RelEvent};
+use crate::sync::SyncEvent;
 use crate::uinput::Uinput;
 
+use std::collections::HashSet;
 use std::ffi::{CStr, OsStr};
-use std::io::Error;
+use std::io::{Error, ErrorKind};
+use std::mem::{self, MaybeUninit};
+use std::os::fd::AsRawFd;
 use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 use std::ptr;
 
 pub struct Writer {
     uinput: Uinput,
+    // Keys and buttons that were written as pressed and not released yet.
+    pressed: HashSet<Key>,
 }
 
 impl Writer {
@@ -37,15 +44,85 @@ impl Writer {
             },
             Event::Key(KeyEvent { down, key }) => (glue::EV_KEY, key.to_raw(), Some(*down as _)),
             Event::Sync(event) => (glue::EV_SYN, event.to_raw(), Some(0)),
+            Event::Led(LedEvent { led, on }) => (glue::EV_LED, led.to_raw(), Some(*on as _)),
         };
 
         if let (Some(code), Some(value)) = (code, value) {
             self.write_raw(r#type as _, code, value).await?;
         }
 
+        if let Event::Key(KeyEvent { key, down }) = event {
+            match down {
+                true => self.pressed.insert(*key),
+                false => self.pressed.remove(key),
+            };
+        }
+
         Ok(())
     }
 
+    pub async fn release(&mut self) -> Result<(), Error> {
+        let pressed = mem::take(&mut self.pressed);
+        if pressed.is_empty() {
+            return Ok(());
+        }
+
+        for key in pressed {
+            self.write(&Event::Key(KeyEvent { key, down: false }))
+                .await?;
+        }
+
+        self.write(&Event::Sync(SyncEvent::All)).await
+    }
+
+    // Reads the next LED change requested by users of the device, such as the display server.
+    //
+    // This is cancel safe.
+    pub async fn read_led(&mut self) -> Result<LedEvent, Error> {
+        loop {
+            let file = self.uinput.file();
+            let result = file.readable().await?.try_io(|_| {
+                let mut event = MaybeUninit::<input_event>::uninit();
+                let ret = unsafe {
+                    libc::read(
+                        file.as_raw_fd(),
+                        event.as_mut_ptr() as *mut _,
+                        mem::size_of::<input_event>(),
+                    )
+                };
+
+                if ret < 0 {
+                    return Err(Error::last_os_error());
+                }
+
+                if ret as usize != mem::size_of::<input_event>() {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Short read from uinput",
+                    ));
+                }
+
+                Ok(unsafe { event.assume_init() })
+            });
+
+            let event = match result {
+                Ok(result) => result?,
+                Err(_) => continue, // This means it would block.
+            };
+
+            if event.type_ as u32 != glue::EV_LED {
+                continue;
+            }
+
+            if let Some(led) = Led::from_raw(event.code) {
+                return Ok(LedEvent {
+                    led,
+                    on: event.value != 0,
+                });
+            }
+        }
+    }
+
     pub fn path(&self) -> Option<&Path> {
         let path = unsafe { glue::libevdev_uinput_get_devnode(self.uinput.as_ptr()) };
         if path.is_null() {
@@ -62,6 +139,7 @@ impl Writer {
     pub(crate) async fn from_evdev(evdev: &Evdev) -> Result<Self, Error> {
         Ok(Self {
             uinput: Uinput::from_evdev(evdev).await?,
+            pressed: HashSet::new(),
         })
     }
 
@@ -238,6 +316,30 @@ impl WriterBuilder {
         Ok(self)
     }
 
+    pub fn led<T: IntoIterator<Item = Led>>(self, items: T) -> Result<Self, Error> {
+        for led in items {
+            let led = match led.to_raw() {
+                Some(led) => led,
+                None => continue,
+            };
+
+            let ret = unsafe {
+                glue::libevdev_enable_event_code(
+                    self.evdev.as_ptr(),
+                    glue::EV_LED,
+                    led as _,
+                    ptr::null(),
+                )
+            };
+
+            if ret < 0 {
+                return Err(Error::from_raw_os_error(-ret));
+            }
+        }
+
+        Ok(self)
+    }
+
     pub fn delay(self, value: Option<i32>) -> Result<Self, Error> {
         let value: c_int = match value {
             Some(value) => value,
@@ -0,0 +1,164 @@
+use clap::{Parser, Subcommand};
+use rkvm_input::interceptor::{Inspector, Properties};
+use std::ffi::OsStr;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
+use tokio::fs;
+
+const EVENT_PATH: &str = "/dev/input";
+
+#[derive(Parser)]
+#[clap(
+    name = "rkvm-inspect",
+    about = "A tool to inspect input devices the way rkvm sees them"
+)]
+struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[clap(
+        about = "List input devices along with their capabilities and whether rkvm can use them"
+    )]
+    List,
+    #[clap(about = "Print events of a device as rkvm decodes them")]
+    Events {
+        path: PathBuf,
+        #[clap(
+            long,
+            short,
+            help = "Grab the device, keeping its events from reaching anyone else"
+        )]
+        grab: bool,
+    },
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let result = match args.command {
+        Command::List => list().await,
+        Command::Events { path, grab } => events(&path, grab).await,
+    };
+
+    if let Err(err) = result {
+        eprintln!("Error: {}", err);
+        return ExitCode::FAILURE;
+    }
+
+    ExitCode::SUCCESS
+}
+
+async fn list() -> Result<(), Error> {
+    let mut paths = Vec::new();
+
+    let mut read_dir = fs::read_dir(EVENT_PATH).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let path = entry.path();
+        let number = path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .and_then(|name| name.strip_prefix("event"))
+            .and_then(|number| number.parse::<u32>().ok());
+
+        if let Some(number) = number {
+            paths.push((number, path));
+        }
+    }
+
+    paths.sort();
+
+    for (_, path) in paths {
+        println!("{}", path.display());
+
+        // Devices can't be opened without sufficient permissions, the rest may still be listed.
+        let inspector = match Inspector::open(&path).await {
+            Ok(inspector) => inspector,
+            Err(err) => {
+                println!("  Error: {}", err);
+                continue;
+            }
+        };
+
+        print(&inspector.properties());
+
+        match inspector.check() {
+            Ok(Some(rejection)) => println!("  Usable: no ({})", rejection),
+            Ok(None) => println!("  Usable: yes"),
+            Err(err) => println!("  Error: {}", err),
+        }
+    }
+
+    Ok(())
+}
+
+fn print(properties: &Properties) {
+    println!("  Name: {}", properties.name().to_string_lossy());
+    println!(
+        "  Id: vendor {:04x}, product {:04x}, version {:04x}",
+        properties.vendor(),
+        properties.product(),
+        properties.version()
+    );
+
+    if let Some(phys) = properties.phys() {
+        println!("  Phys: {}", phys.to_string_lossy());
+    }
+
+    let rel = properties
+        .rel()
+        .map(|axis| format!("{:?}", axis))
+        .collect::<Vec<_>>();
+
+    if !rel.is_empty() {
+        println!("  Rel: {}", rel.join(", "));
+    }
+
+    for (axis, info) in properties.abs() {
+        // Devices with such axes are rejected.
+        let note = if info.is_nonsense() {
+            " (nonsense)"
+        } else {
+            ""
+        };
+
+        println!(
+            "  Abs: {:?}, min {}, max {}, fuzz {}, flat {}, resolution {}{}",
+            axis, info.min, info.max, info.fuzz, info.flat, info.resolution, note
+        );
+    }
+
+    let keys = properties
+        .key()
+        .map(|key| format!("{:?}", key))
+        .collect::<Vec<_>>();
+
+    if !keys.is_empty() {
+        println!("  Keys: {}", keys.join(", "));
+    }
+
+    let repeat = properties.repeat();
+    if let (Some(delay), Some(period)) = (repeat.delay, repeat.period) {
+        println!("  Repeat: delay {}ms, period {}ms", delay, period);
+    }
+}
+
+async fn events(path: &Path, grab: bool) -> Result<(), Error> {
+    let mut inspector = Inspector::open(path).await?;
+    print(&inspector.properties());
+
+    if grab {
+        inspector.grab()?;
+    }
+
+    let start = Instant::now();
+    loop {
+        let event = inspector.read().await?;
+        println!("{:>12.6} {:?}", start.elapsed().as_secs_f64(), event);
+    }
+}
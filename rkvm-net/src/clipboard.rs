@@ -0,0 +1,274 @@
+use std::future::Future;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::time::{self, Interval, MissedTickBehavior};
+
+// How often clipboards are checked for changes.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Longer contents are not shared, as they wouldn't fit into a single message.
+pub const MAX_LEN: usize = 60 * 1024;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// Access to the text contents of a clipboard.
+pub trait ClipboardBackend: Send {
+    // Returns None if the clipboard is empty or doesn't contain text.
+    fn read(&mut self) -> BoxFuture<'_, Result<Option<String>, Error>>;
+
+    fn write<'a>(&'a mut self, text: &'a str) -> BoxFuture<'a, Result<(), Error>>;
+}
+
+// Runs external programs reading the clipboard from their standard output and writing it from their standard input.
+pub struct CommandBackend {
+    read: Vec<String>,
+    write: Vec<String>,
+}
+
+impl CommandBackend {
+    pub fn new(read: Vec<String>, write: Vec<String>) -> Self {
+        Self { read, write }
+    }
+
+    pub fn wayland() -> Self {
+        Self::new(
+            vec!["wl-paste".to_owned(), "--no-newline".to_owned()],
+            vec!["wl-copy".to_owned()],
+        )
+    }
+
+    pub fn x11() -> Self {
+        let args = |mode: &str| {
+            ["xclip", "-selection", "clipboard", mode]
+                .into_iter()
+                .map(ToOwned::to_owned)
+                .collect()
+        };
+
+        Self::new(args("-o"), args("-i"))
+    }
+}
+
+impl ClipboardBackend for CommandBackend {
+    fn read(&mut self) -> BoxFuture<'_, Result<Option<String>, Error>> {
+        Box::pin(async move {
+            let (program, args) = split(&self.read)?;
+            let output = Command::new(program)
+                .args(args)
+                .stdin(Stdio::null())
+                .stderr(Stdio::null())
+                .output()
+                .await?;
+
+            // Both wl-paste and xclip fail if there is nothing to paste.
+            if !output.status.success() {
+                return Ok(None);
+            }
+
+            Ok(String::from_utf8(output.stdout).ok())
+        })
+    }
+
+    fn write<'a>(&'a mut self, text: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let (program, args) = split(&self.write)?;
+            let mut child = Command::new(program)
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()?;
+
+            // Dropping stdin closes it, letting the program know the text is complete.
+            let mut stdin = child.stdin.take().unwrap();
+            stdin.write_all(text.as_bytes()).await?;
+            drop(stdin);
+
+            let status = child.wait().await?;
+            if !status.success() {
+                return Err(Error::other(format!("{} exited with {}", program, status)));
+            }
+
+            Ok(())
+        })
+    }
+}
+
+fn split(command: &[String]) -> Result<(&str, &[String]), Error> {
+    match command {
+        [program, args @ ..] => Ok((program, args)),
+        [] => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Empty clipboard command",
+        )),
+    }
+}
+
+// Keeps the clipboard in a plain file, mostly useful for testing.
+pub struct FileBackend {
+    path: PathBuf,
+}
+
+impl FileBackend {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl ClipboardBackend for FileBackend {
+    fn read(&mut self) -> BoxFuture<'_, Result<Option<String>, Error>> {
+        Box::pin(async move {
+            match fs::read_to_string(&self.path).await {
+                Ok(text) => Ok(Some(text)),
+                Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+                Err(err) if err.kind() == ErrorKind::InvalidData => Ok(None),
+                Err(err) => Err(err),
+            }
+        })
+    }
+
+    fn write<'a>(&'a mut self, text: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(fs::write(&self.path, text))
+    }
+}
+
+// Watches a clipboard for changes by polling it on its own task, since backends can be slow to respond.
+// Dropping it stops the task.
+pub struct Clipboard {
+    changes: Receiver<Result<String, Error>>,
+    contents: UnboundedSender<String>,
+}
+
+impl Clipboard {
+    // Contents present at the time of creation are not reported as a change.
+    pub async fn new(
+        mut backend: Box<dyn ClipboardBackend>,
+        period: Duration,
+    ) -> Result<Self, Error> {
+        let last = backend.read().await?;
+
+        let mut interval = time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let (changes_sender, changes_receiver) = mpsc::channel(1);
+        let (contents_sender, contents_receiver) = mpsc::unbounded_channel();
+
+        tokio::spawn(watch(
+            backend,
+            interval,
+            last,
+            changes_sender,
+            contents_receiver,
+        ));
+
+        Ok(Self {
+            changes: changes_receiver,
+            contents: contents_sender,
+        })
+    }
+
+    // Waits until the contents change and returns them. Errors of setting the contents are returned here as well,
+    // after which the clipboard stops working. This function is cancel safe.
+    pub async fn changed(&mut self) -> Result<String, Error> {
+        self.changes
+            .recv()
+            .await
+            .unwrap_or_else(|| Err(Error::new(ErrorKind::BrokenPipe, "Clipboard task exited")))
+    }
+
+    // Replaces the contents without reporting them as a change, without waiting for that to happen.
+    pub fn set(&self, text: String) {
+        let _ = self.contents.send(text);
+    }
+}
+
+async fn watch(
+    mut backend: Box<dyn ClipboardBackend>,
+    mut interval: Interval,
+    // Contents as last seen or set, changes are only reported relative to these.
+    mut last: Option<String>,
+    changes: Sender<Result<String, Error>>,
+    mut contents: UnboundedReceiver<String>,
+) {
+    loop {
+        let result = tokio::select! {
+            _ = interval.tick() => match backend.read().await {
+                Ok(Some(text)) if last.as_ref() != Some(&text) => {
+                    last = Some(text.clone());
+
+                    if text.len() > MAX_LEN {
+                        tracing::warn!(len = %text.len(), "Not sharing clipboard contents, they are too long");
+                        continue;
+                    }
+
+                    Ok(text)
+                }
+                Ok(_) => continue,
+                Err(err) => Err(err),
+            },
+            text = contents.recv() => {
+                let text = match text {
+                    Some(text) => text,
+                    None => return,
+                };
+
+                if last.as_ref() == Some(&text) {
+                    continue;
+                }
+
+                match backend.write(&text).await {
+                    Ok(()) => {
+                        last = Some(text);
+                        continue;
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            _ = changes.closed() => return,
+        };
+
+        let failed = result.is_err();
+        if changes.send(result).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[tokio::test]
+    async fn reports_changes() {
+        let path = env::temp_dir().join(format!("rkvm-clipboard-{}", process::id()));
+        fs::write(&path, "before").await.unwrap();
+
+        let backend = Box::new(FileBackend::new(path.clone()));
+        let mut clipboard = Clipboard::new(backend, Duration::from_millis(10))
+            .await
+            .unwrap();
+
+        fs::write(&path, "copied").await.unwrap();
+        assert_eq!(clipboard.changed().await.unwrap(), "copied");
+
+        // Contents set from the other side are not echoed back.
+        clipboard.set("pasted".to_owned());
+        assert!(
+            time::timeout(Duration::from_millis(50), clipboard.changed())
+                .await
+                .is_err()
+        );
+        assert_eq!(fs::read_to_string(&path).await.unwrap(), "pasted");
+
+        fs::remove_file(&path).await.unwrap();
+    }
+}
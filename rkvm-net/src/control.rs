@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+// Requests sent over the server's local control socket, each one is answered by exactly one response.
+#[derive(Deserialize, Serialize, Debug)]
+pub enum Request {
+    Clients,
+    Devices,
+    Focus,
+    Switch { target: String },
+    Disconnect { name: String },
+    // Reads the configuration again, keeping the current one if it's invalid.
+    Reload,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub enum Response {
+    Clients(Vec<ClientInfo>),
+    Devices(Vec<DeviceInfo>),
+    Focus(String),
+    Done,
+    Error(String),
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ClientInfo {
+    pub name: String,
+    pub addr: SocketAddr,
+    // Number of events dropped because the client couldn't keep up.
+    pub dropped: u64,
+    // How long the oldest update queued for the client has been waiting for.
+    pub lag: Duration,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DeviceInfo {
+    pub id: usize,
+    pub name: String,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+    // Number of events dropped because the device couldn't keep up when written to locally.
+    pub dropped: u64,
+}
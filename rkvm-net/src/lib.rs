@@ -2,12 +2,16 @@
 #![allow(async_fn_in_trait)]
 
 pub mod auth;
+pub mod clipboard;
+pub mod control;
 pub mod message;
+pub mod recording;
 pub mod version;
 
 use rkvm_input::abs::{AbsAxis, AbsInfo};
 use rkvm_input::event::Event;
 use rkvm_input::key::Key;
+use rkvm_input::led::LedEvent;
 use rkvm_input::rel::RelAxis;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
@@ -50,11 +54,34 @@ pub enum Update {
         event: Event,
     },
     Ping,
+    // Events of a device up to and including the SyncEvent::All terminating the frame.
+    Frame {
+        id: usize,
+        events: Vec<Event>,
+    },
+    // Latest clipboard contents, sent when the client gets focused if Capabilities::CLIPBOARD has been negotiated.
+    Clipboard(String),
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Pong;
 
+// Sent by the client instead of Pong if any of Capabilities::REPORTS has been negotiated.
+#[derive(Deserialize, Serialize, Debug)]
+pub enum Report {
+    Pong,
+    // The LED of the client's devices changed, all of them are expected to share the same state.
+    Led(LedEvent),
+    // The clipboard contents of the client changed.
+    Clipboard(String),
+}
+
+// Sent by the client after successful authentication to announce its name.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Hello {
+    pub name: String,
+}
+
 pub async fn timeout<T: Future<Output = Result<U, Error>>, U>(
     duration: Duration,
     future: T,
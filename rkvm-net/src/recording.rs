@@ -0,0 +1,124 @@
+use crate::message::Message;
+use crate::version::Version;
+use crate::Update;
+
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const MAGIC: [u8; 8] = *b"RKVMREC\0";
+
+// Starts every recording, updates are encoded the same way as on the wire using the given protocol version.
+pub struct Header {
+    pub version: Version,
+}
+
+impl Header {
+    pub const CURRENT: Self = Self {
+        version: Version::CURRENT,
+    };
+}
+
+impl Message for Header {
+    async fn decode<R: AsyncRead + Send + Unpin>(stream: &mut R) -> Result<Self, Error> {
+        let mut magic = [0; MAGIC.len()];
+        stream.read_exact(&mut magic).await?;
+
+        if magic != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Not an rkvm recording"));
+        }
+
+        let version = Version::decode(stream).await?;
+        if version != Version::CURRENT {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Recording uses protocol {}, expected {}",
+                    version,
+                    Version::CURRENT
+                ),
+            ));
+        }
+
+        Ok(Self { version })
+    }
+
+    async fn encode<W: AsyncWrite + Send + Unpin>(&self, stream: &mut W) -> Result<(), Error> {
+        stream.write_all(&MAGIC).await?;
+        self.version.encode(stream).await
+    }
+}
+
+// An update along with the time elapsed since the recording started.
+pub struct Record {
+    pub elapsed: Duration,
+    pub update: Update,
+}
+
+impl Message for Record {
+    async fn decode<R: AsyncRead + Send + Unpin>(stream: &mut R) -> Result<Self, Error> {
+        let elapsed = stream.read_u64_le().await.map(Duration::from_micros)?;
+        let update = Update::decode(stream).await?;
+
+        Ok(Self { elapsed, update })
+    }
+
+    async fn encode<W: AsyncWrite + Send + Unpin>(&self, stream: &mut W) -> Result<(), Error> {
+        let elapsed = self.elapsed.as_micros().try_into().unwrap_or(u64::MAX);
+
+        stream.write_u64_le(elapsed).await?;
+        self.update.encode(stream).await
+    }
+}
+
+// Reads the next record, returning None at the end of the recording, including one that has been cut short.
+pub async fn read<R: AsyncRead + Send + Unpin>(stream: &mut R) -> Result<Option<Record>, Error> {
+    match Record::decode(stream).await {
+        Ok(record) => Ok(Some(record)),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rkvm_input::event::Event;
+    use rkvm_input::rel::{RelAxis, RelEvent};
+
+    #[tokio::test]
+    async fn records_round_trip() {
+        let mut data = Vec::new();
+        Header::CURRENT.encode(&mut data).await.unwrap();
+
+        let record = Record {
+            elapsed: Duration::from_millis(1500),
+            update: Update::Event {
+                id: 3,
+                event: Event::Rel(RelEvent {
+                    axis: RelAxis::X,
+                    value: -7,
+                }),
+            },
+        };
+        record.encode(&mut data).await.unwrap();
+
+        let mut stream = data.as_slice();
+        Header::decode(&mut stream).await.unwrap();
+
+        let record = read(&mut stream).await.unwrap().unwrap();
+        assert_eq!(record.elapsed, Duration::from_millis(1500));
+        assert!(matches!(
+            record.update,
+            Update::Event {
+                id: 3,
+                event: Event::Rel(RelEvent {
+                    axis: RelAxis::X,
+                    value: -7,
+                }),
+            }
+        ));
+
+        assert!(read(&mut stream).await.unwrap().is_none());
+    }
+}
@@ -2,13 +2,26 @@ use crate::message::Message;
 
 use std::fmt::{self, Display, Formatter};
 use std::io::Error;
+use std::ops::BitAnd;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Version(u16);
 
 impl Version {
-    pub const CURRENT: Self = Self(5);
+    pub const CURRENT: Self = Self(8);
+
+    // Oldest version still served.
+    pub const MIN: Self = Self(5);
+
+    // Clients announce their name using Hello starting with this version.
+    pub const HELLO: Self = Self(6);
+
+    // Events are sent as Update::Frame starting with this version.
+    pub const FRAMES: Self = Self(7);
+
+    // Starting with this version, the version is followed by a Handshake instead of requiring an exact match.
+    pub const NEGOTIATION: Self = Self(8);
 }
 
 impl Display for Version {
@@ -26,3 +39,150 @@ impl Message for Version {
         stream.write_u16_le(self.0).await
     }
 }
+
+// Optional protocol features, which are only used if both sides support them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities(u64);
+
+impl Capabilities {
+    pub const NONE: Self = Self(0);
+
+    // The client sends Report instead of Pong and reports LED changes of its devices.
+    pub const LEDS: Self = Self(1 << 0);
+
+    // Clipboard contents are exchanged using Update::Clipboard and Report::Clipboard.
+    pub const CLIPBOARD: Self = Self(1 << 1);
+
+    // Capabilities requiring the client to send Report instead of Pong.
+    pub const REPORTS: Self = Self(Self::LEDS.0 | Self::CLIPBOARD.0);
+
+    // Capabilities supported by this build.
+    pub const SUPPORTED: Self = Self(Self::LEDS.0 | Self::CLIPBOARD.0);
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(&self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl BitAnd for Capabilities {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+// Range of supported versions along with capabilities, sent by both sides after their version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Handshake {
+    pub min: Version,
+    pub max: Version,
+    pub capabilities: Capabilities,
+}
+
+impl Handshake {
+    pub const CURRENT: Self = Self {
+        min: Version::MIN,
+        max: Version::CURRENT,
+        capabilities: Capabilities::SUPPORTED,
+    };
+
+    // Peers older than Version::NEGOTIATION only speak their own version.
+    pub fn exact(version: Version) -> Self {
+        Self {
+            min: version,
+            max: version,
+            capabilities: Capabilities::NONE,
+        }
+    }
+
+    // Settles on the highest version and capabilities supported by both sides.
+    pub fn negotiate(&self, peer: &Self) -> Option<Negotiated> {
+        let version = self.max.min(peer.max);
+        if version < self.min.max(peer.min) {
+            return None;
+        }
+
+        Some(Negotiated {
+            version,
+            capabilities: self.capabilities & peer.capabilities,
+        })
+    }
+}
+
+impl Display for Handshake {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.min == self.max {
+            return write!(f, "{}", self.max);
+        }
+
+        write!(f, "{} to {}", self.min, self.max)
+    }
+}
+
+impl Message for Handshake {
+    async fn decode<R: AsyncRead + Send + Unpin>(stream: &mut R) -> Result<Self, Error> {
+        let min = Version::decode(stream).await?;
+        let max = Version::decode(stream).await?;
+        let capabilities = stream.read_u64_le().await.map(Capabilities)?;
+
+        Ok(Self {
+            min,
+            max,
+            capabilities,
+        })
+    }
+
+    async fn encode<W: AsyncWrite + Send + Unpin>(&self, stream: &mut W) -> Result<(), Error> {
+        self.min.encode(stream).await?;
+        self.max.encode(stream).await?;
+        stream.write_u64_le(self.capabilities.0).await
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Negotiated {
+    pub version: Version,
+    pub capabilities: Capabilities,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn negotiates_highest_common_version() {
+        let peer = Handshake {
+            min: Version(7),
+            max: Version(9),
+            capabilities: Capabilities(0b11),
+        };
+
+        let ours = Handshake {
+            capabilities: Capabilities(0b01),
+            ..Handshake::CURRENT
+        };
+
+        let negotiated = ours.negotiate(&peer).unwrap();
+        assert_eq!(negotiated.version, Version::CURRENT);
+        assert_eq!(negotiated.capabilities, Capabilities(0b01));
+    }
+
+    #[test]
+    fn serves_legacy_versions() {
+        let negotiated = Handshake::CURRENT
+            .negotiate(&Handshake::exact(Version::MIN))
+            .unwrap();
+
+        assert_eq!(negotiated.version, Version::MIN);
+        assert_eq!(negotiated.capabilities, Capabilities::NONE);
+
+        assert!(Handshake::CURRENT
+            .negotiate(&Handshake::exact(Version(4)))
+            .is_none());
+    }
+}
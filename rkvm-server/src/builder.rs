@@ -0,0 +1,443 @@
+use crate::control;
+use crate::devices::{Action, Rule, Rules};
+use crate::handle::Server;
+use crate::layout::{Layout, Screen};
+use crate::pointer;
+use crate::recorder::Recorder;
+use crate::remap::Remap;
+use crate::server::{Error, Profile, Settings, Switching, LOCAL_TARGET};
+use crate::tls::{self, ClientAuth};
+
+use rkvm_input::key::Key;
+use rkvm_net::clipboard::{self, Clipboard, ClipboardBackend};
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::net::TcpListener;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("No listen addresses configured")]
+    NoListen,
+    #[error("No certificate configured")]
+    NoCertificate,
+    #[error("Neither a password nor client certificates are configured")]
+    NoAuth,
+    #[error("Duplicate target {0:?}")]
+    DuplicateTarget(String),
+    #[error("Only clients can have their keys remapped or pointer adjusted, not target {0:?}")]
+    Profile(String),
+    #[error("Invalid pointer settings of target {0:?}")]
+    Pointer(String),
+    #[error("No switch keys or screen provided for target {0:?}")]
+    Unreachable(String),
+    #[error("Invalid members of broadcast target {0:?}")]
+    Members(String),
+    #[error("Broadcast target {0:?} can't have a screen")]
+    GroupScreen(String),
+    #[error("Empty screen of target {0:?}")]
+    EmptyScreen(String),
+    #[error("Neighbour {neighbour:?} of target {name:?} has no screen")]
+    Neighbour { name: String, neighbour: String },
+    #[error("Overlapping switch keys")]
+    OverlappingSwitchKeys,
+    #[error("Devices can't be pinned to broadcast target {0:?}")]
+    PinnedToGroup(String),
+}
+
+// Describes a target, the one named LOCAL_TARGET being the server itself and others being clients,
+// unless they have members.
+#[derive(Clone, Default)]
+pub struct Target {
+    pub name: String,
+    // Keys focusing the target directly.
+    pub switch_keys: HashSet<Key>,
+    pub screen: Option<Screen>,
+    // Clients all input is mirrored to while the target is focused.
+    pub members: Vec<String>,
+    // Keys replaced by other keys or combinations before being sent to the client.
+    pub remap: HashMap<Key, Vec<Key>>,
+    pub pointer: Option<pointer::Settings>,
+}
+
+// Produces the configuration anew each time the server is asked to reload it.
+pub type Loader = dyn Fn()
+        -> Pin<Box<dyn Future<Output = Result<Builder, Box<dyn error::Error + Send + Sync>>> + Send>>
+    + Send
+    + Sync;
+
+pub struct Builder {
+    listen: Vec<SocketAddr>,
+    certificate: Option<(PathBuf, PathBuf)>,
+    client_ca: Option<PathBuf>,
+    client_certificates: Vec<PathBuf>,
+    password: Option<String>,
+    switch_keys: HashSet<Key>,
+    propagate_switch_keys: bool,
+    edge_lock_keys: HashSet<Key>,
+    targets: Vec<Target>,
+    rules: Vec<Rule>,
+    clipboard: Option<Box<dyn ClipboardBackend>>,
+    record: Option<PathBuf>,
+    control_socket: Option<PathBuf>,
+    loader: Option<Arc<Loader>>,
+}
+
+impl Builder {
+    pub(crate) fn new() -> Self {
+        Self {
+            listen: Vec::new(),
+            certificate: None,
+            client_ca: None,
+            client_certificates: Vec::new(),
+            password: None,
+            switch_keys: HashSet::new(),
+            propagate_switch_keys: true,
+            edge_lock_keys: HashSet::new(),
+            targets: Vec::new(),
+            rules: Vec::new(),
+            clipboard: None,
+            record: None,
+            control_socket: None,
+            loader: None,
+        }
+    }
+
+    // Can be called multiple times to listen on several addresses.
+    pub fn listen(mut self, addr: SocketAddr) -> Self {
+        self.listen.push(addr);
+        self
+    }
+
+    pub fn certificate(mut self, certificate: PathBuf, key: PathBuf) -> Self {
+        self.certificate = Some((certificate, key));
+        self
+    }
+
+    // CA bundle client certificates can be issued by.
+    pub fn client_ca(mut self, path: PathBuf) -> Self {
+        self.client_ca = Some(path);
+        self
+    }
+
+    // Can be called multiple times to trust several client certificates as they are.
+    pub fn client_certificate(mut self, path: PathBuf) -> Self {
+        self.client_certificates.push(path);
+        self
+    }
+
+    // Without a password, clients have to present a certificate.
+    pub fn password(mut self, password: String) -> Self {
+        self.password = Some(password);
+        self
+    }
+
+    // Keys cycling through the server and all connected clients.
+    pub fn switch_keys<T: IntoIterator<Item = Key>>(mut self, keys: T) -> Self {
+        self.switch_keys = keys.into_iter().collect();
+        self
+    }
+
+    pub fn propagate_switch_keys(mut self, propagate: bool) -> Self {
+        self.propagate_switch_keys = propagate;
+        self
+    }
+
+    // Keys keeping the pointer on the current screen while held.
+    pub fn edge_lock_keys<T: IntoIterator<Item = Key>>(mut self, keys: T) -> Self {
+        self.edge_lock_keys = keys.into_iter().collect();
+        self
+    }
+
+    pub fn target(mut self, target: Target) -> Self {
+        self.targets.push(target);
+        self
+    }
+
+    // Rules are tried in the order they are added.
+    pub fn device(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn clipboard(mut self, backend: Box<dyn ClipboardBackend>) -> Self {
+        self.clipboard = Some(backend);
+        self
+    }
+
+    // Records everything the devices do into a file.
+    pub fn record(mut self, path: PathBuf) -> Self {
+        self.record = Some(path);
+        self
+    }
+
+    pub fn control_socket(mut self, path: PathBuf) -> Self {
+        self.control_socket = Some(path);
+        self
+    }
+
+    // Makes the server able to reload its configuration. Listen addresses, the control socket, the clipboard
+    // and recording are only set up once, the same parts of loaded configurations are ignored.
+    pub fn loader<F, T, E>(mut self, loader: F) -> Self
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+        T: Future<Output = Result<Builder, E>> + Send + 'static,
+        E: Into<Box<dyn error::Error + Send + Sync>>,
+    {
+        self.loader = Some(Arc::new(move || {
+            let future = loader();
+            Box::pin(async move { future.await.map_err(Into::into) })
+        }));
+        self
+    }
+
+    // Validates the configuration and starts the server.
+    pub async fn build(mut self) -> Result<Server, Error> {
+        if self.listen.is_empty() {
+            return Err(ConfigError::NoListen.into());
+        }
+
+        let settings = self.settings().await?;
+
+        let mut listeners = Vec::new();
+        for addr in &self.listen {
+            let listener = TcpListener::bind(addr).await.map_err(Error::Network)?;
+            tracing::info!("Listening on {}", addr);
+
+            listeners.push(listener);
+        }
+
+        let control_listener = match &self.control_socket {
+            Some(path) => {
+                let listener = control::bind(path).await.map_err(Error::Control)?;
+                tracing::info!("Listening for control requests on {:?}", path);
+
+                Some(listener)
+            }
+            None => None,
+        };
+
+        let clipboard = match self.clipboard {
+            Some(backend) => Some(
+                Clipboard::new(backend, clipboard::POLL_INTERVAL)
+                    .await
+                    .map_err(Error::Clipboard)?,
+            ),
+            None => None,
+        };
+
+        let recorder = match &self.record {
+            Some(path) => {
+                let recorder = Recorder::create(path).await.map_err(Error::Recording)?;
+                tracing::info!("Recording to {:?}", path);
+
+                Some(recorder)
+            }
+            None => None,
+        };
+
+        Ok(Server::start(
+            settings,
+            clipboard,
+            recorder,
+            listeners,
+            control_listener,
+            self.loader,
+        ))
+    }
+
+    // Borrowed mutably only so that the future is Send, since clipboard backends aren't Sync.
+    pub(crate) async fn settings(&mut self) -> Result<Settings, Error> {
+        if self.password.is_none()
+            && self.client_ca.is_none()
+            && self.client_certificates.is_empty()
+        {
+            return Err(ConfigError::NoAuth.into());
+        }
+
+        let (certificate, key) = self
+            .certificate
+            .as_ref()
+            .ok_or(ConfigError::NoCertificate)?;
+        let (switching, rules, profiles) = self.routing()?;
+
+        let client_auth = ClientAuth {
+            ca: self.client_ca.as_deref(),
+            certificates: &self.client_certificates,
+            // Clients without a certificate can only authenticate using the password.
+            mandatory: self.password.is_none(),
+        };
+
+        let acceptor = tls::configure(certificate, key, client_auth)
+            .await
+            .map_err(Error::Tls)?;
+
+        Ok(Settings {
+            switching,
+            rules,
+            profiles,
+            password: self.password.clone(),
+            acceptor,
+        })
+    }
+
+    fn routing(&self) -> Result<(Switching, Rules, HashMap<String, Profile>), ConfigError> {
+        let mut names = HashSet::new();
+        let mut targets = HashMap::new();
+        let mut groups = HashMap::new();
+        let mut profiles = HashMap::new();
+        let mut screens = HashMap::new();
+
+        for target in self.targets.iter().cloned() {
+            if !names.insert(target.name.clone()) {
+                return Err(ConfigError::DuplicateTarget(target.name));
+            }
+
+            if !target.remap.is_empty() || target.pointer.is_some() {
+                // Events written locally are limited to what the physical device supports.
+                if target.name == LOCAL_TARGET || !target.members.is_empty() {
+                    return Err(ConfigError::Profile(target.name));
+                }
+
+                let pointer = target.pointer.unwrap_or_default();
+
+                let valid = |value: f64| value.is_finite() && value >= 0.0;
+                if !valid(pointer.speed)
+                    || !valid(pointer.acceleration)
+                    || !valid(pointer.wheel_speed)
+                {
+                    return Err(ConfigError::Pointer(target.name));
+                }
+
+                let profile = Profile {
+                    remap: Arc::new(Remap(target.remap)),
+                    pointer,
+                };
+
+                profiles.insert(target.name.clone(), profile);
+            }
+
+            if target.switch_keys.is_empty()
+                && target.screen.is_none()
+                && target.members.is_empty()
+                && !profiles.contains_key(&target.name)
+            {
+                return Err(ConfigError::Unreachable(target.name));
+            }
+
+            if !target.switch_keys.is_empty() {
+                targets.insert(target.name.clone(), target.switch_keys);
+            }
+
+            if !target.members.is_empty() {
+                if target.name == LOCAL_TARGET
+                    || target.members.contains(&target.name)
+                    || target.members.iter().any(|name| name == LOCAL_TARGET)
+                {
+                    return Err(ConfigError::Members(target.name));
+                }
+
+                // The pointer would have to be on all screens of the members at once.
+                if target.screen.is_some() {
+                    return Err(ConfigError::GroupScreen(target.name));
+                }
+
+                groups.insert(target.name.clone(), target.members.into());
+            }
+
+            if let Some(screen) = target.screen {
+                if screen.width <= 0 || screen.height <= 0 {
+                    return Err(ConfigError::EmptyScreen(target.name));
+                }
+
+                screens.insert(target.name, screen);
+            }
+        }
+
+        for (name, screen) in &screens {
+            for neighbour in screen.neighbours.values() {
+                if !screens.contains_key(neighbour) {
+                    return Err(ConfigError::Neighbour {
+                        name: name.clone(),
+                        neighbour: neighbour.clone(),
+                    });
+                }
+            }
+        }
+
+        // A binding that contains all keys of another one could never be triggered,
+        // because the smaller one would always fire first.
+        // Empty ones are skipped, as they are never triggered and would be contained in every other one.
+        let bindings = [&self.switch_keys]
+            .into_iter()
+            .chain(targets.values())
+            .filter(|keys| !keys.is_empty())
+            .collect::<Vec<_>>();
+
+        for (i, a) in bindings.iter().enumerate() {
+            for (j, b) in bindings.iter().enumerate() {
+                if i != j && a.is_subset(b) {
+                    return Err(ConfigError::OverlappingSwitchKeys);
+                }
+            }
+        }
+
+        for rule in &self.rules {
+            if let Action::Pin(target) = &rule.action {
+                if groups.contains_key(target) {
+                    return Err(ConfigError::PinnedToGroup(target.clone()));
+                }
+            }
+        }
+
+        let switching = Switching {
+            switch_keys: self.switch_keys.clone(),
+            targets,
+            groups,
+            propagate_switch_keys: self.propagate_switch_keys,
+            layout: Layout::new(screens, self.edge_lock_keys.clone()),
+        };
+
+        Ok((switching, Rules(self.rules.clone()), profiles))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rkvm_input::key::Keyboard;
+
+    fn target(name: &str, keys: &[Keyboard]) -> Target {
+        Target {
+            name: name.to_owned(),
+            switch_keys: keys.iter().map(|key| Key::Key(*key)).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn target_keys_without_switch_keys() {
+        let builder = Builder::new()
+            .target(target("a", &[Keyboard::LeftAlt, Keyboard::A]))
+            .target(target("b", &[Keyboard::LeftAlt, Keyboard::B]));
+
+        assert!(builder.routing().is_ok());
+    }
+
+    #[test]
+    fn overlapping_target_keys() {
+        let builder = Builder::new()
+            .switch_keys([Key::Key(Keyboard::LeftAlt)])
+            .target(target("a", &[Keyboard::LeftAlt, Keyboard::A]));
+
+        assert!(matches!(
+            builder.routing(),
+            Err(ConfigError::OverlappingSwitchKeys)
+        ));
+    }
+}
@@ -1,6 +1,10 @@
+use rkvm_input::interceptor;
 use rkvm_input::key::{Button, Key, Keyboard};
+use rkvm_net::clipboard::{ClipboardBackend, CommandBackend, FileBackend};
+use rkvm_server::{Edge, PointerSettings};
+use serde::de::{Deserializer, IntoDeserializer};
 use serde::Deserialize;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::path::PathBuf;
 
@@ -10,9 +14,196 @@ pub struct Config {
     pub listen: SocketAddr,
     pub certificate: PathBuf,
     pub key: PathBuf,
-    pub password: String,
+    pub password: Option<String>,
+    pub client_ca: Option<PathBuf>,
+    #[serde(default)]
+    pub client_certificates: Vec<PathBuf>,
     pub switch_keys: HashSet<SwitchKey>,
     pub propagate_switch_keys: Option<bool>,
+    pub control_socket: Option<PathBuf>,
+    #[serde(default)]
+    pub targets: Vec<Target>,
+    #[serde(default)]
+    pub edge_lock_keys: HashSet<SwitchKey>,
+    #[serde(default)]
+    pub devices: Vec<Device>,
+    pub clipboard: Option<Clipboard>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Clipboard {
+    Wayland,
+    X11,
+    File(PathBuf),
+}
+
+impl From<Clipboard> for Box<dyn ClipboardBackend> {
+    fn from(clipboard: Clipboard) -> Self {
+        match clipboard {
+            Clipboard::Wayland => Box::new(CommandBackend::wayland()),
+            Clipboard::X11 => Box::new(CommandBackend::x11()),
+            Clipboard::File(path) => Box::new(FileBackend::new(path)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Target {
+    pub name: String,
+    #[serde(default)]
+    pub switch_keys: HashSet<SwitchKey>,
+    pub screen: Option<Screen>,
+    // Turns the target into a broadcast group mirroring input to all of the listed clients.
+    #[serde(default)]
+    pub members: Vec<String>,
+    #[serde(default, deserialize_with = "deserialize_remap")]
+    pub remap: HashMap<SwitchKey, Remap>,
+    pub pointer: Option<Pointer>,
+}
+
+impl From<Target> for rkvm_server::Target {
+    fn from(target: Target) -> Self {
+        Self {
+            name: target.name,
+            switch_keys: target.switch_keys.into_iter().map(Into::into).collect(),
+            screen: target.screen.map(Into::into),
+            members: target.members,
+            remap: target
+                .remap
+                .into_iter()
+                .map(|(from, to)| (from.into(), to.into()))
+                .collect(),
+            pointer: target.pointer.map(Into::into),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Pointer {
+    pub speed: Option<f64>,
+    pub acceleration: Option<f64>,
+    pub wheel_speed: Option<f64>,
+    #[serde(default)]
+    pub natural_scroll: bool,
+}
+
+impl From<Pointer> for PointerSettings {
+    fn from(pointer: Pointer) -> Self {
+        let default = Self::default();
+
+        Self {
+            speed: pointer.speed.unwrap_or(default.speed),
+            acceleration: pointer.acceleration.unwrap_or(default.acceleration),
+            wheel_speed: pointer.wheel_speed.unwrap_or(default.wheel_speed),
+            natural_scroll: pointer.natural_scroll,
+        }
+    }
+}
+
+// Table keys are always strings, which toml doesn't deserialize enums from.
+fn deserialize_remap<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<SwitchKey, Remap>, D::Error> {
+    HashMap::<String, Remap>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(key, remap)| {
+            let key = SwitchKey::deserialize(key.as_str().into_deserializer())?;
+            Ok((key, remap))
+        })
+        .collect()
+}
+
+// Either a single key or a combination of keys.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Remap {
+    Key(SwitchKey),
+    Combination(Vec<SwitchKey>),
+}
+
+impl From<Remap> for Vec<Key> {
+    fn from(remap: Remap) -> Self {
+        match remap {
+            Remap::Key(key) => vec![key.into()],
+            Remap::Combination(keys) => keys.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Screen {
+    pub width: u16,
+    pub height: u16,
+    pub left: Option<String>,
+    pub right: Option<String>,
+    pub top: Option<String>,
+    pub bottom: Option<String>,
+}
+
+impl From<Screen> for rkvm_server::Screen {
+    fn from(screen: Screen) -> Self {
+        let neighbours = [
+            (Edge::Left, screen.left),
+            (Edge::Right, screen.right),
+            (Edge::Top, screen.top),
+            (Edge::Bottom, screen.bottom),
+        ]
+        .into_iter()
+        .filter_map(|(edge, name)| name.map(|name| (edge, name)))
+        .collect();
+
+        Self {
+            width: screen.width.into(),
+            height: screen.height.into(),
+            neighbours,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Device {
+    pub name: Option<String>,
+    pub vendor: Option<u16>,
+    pub product: Option<u16>,
+    pub phys: Option<String>,
+    #[serde(default)]
+    pub capabilities: HashSet<Capability>,
+    pub action: Action,
+    // Only valid along with the forward action.
+    pub target: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Ignore,
+    LocalOnly,
+    Forward,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum Capability {
+    Rel,
+    Abs,
+    Key,
+    Led,
+}
+
+impl From<Capability> for interceptor::Capability {
+    fn from(capability: Capability) -> Self {
+        match capability {
+            Capability::Rel => Self::Rel,
+            Capability::Abs => Self::Abs,
+            Capability::Key => Self::Key,
+            Capability::Led => Self::Led,
+        }
+    }
 }
 
 #[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
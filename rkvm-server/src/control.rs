@@ -0,0 +1,159 @@
+use crate::builder::Loader;
+use crate::handle;
+use crate::server::Settings;
+
+use rkvm_net::control::{Request, Response};
+use rkvm_net::message::Message;
+use std::fs::Permissions;
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use tokio::fs::{self, DirBuilder};
+use tokio::io::{AsyncWriteExt, BufStream};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+
+pub enum Command {
+    Request(Request, oneshot::Sender<Response>),
+    // Validated settings replacing the current ones, the reply is sent once they have been applied.
+    Reload(Box<Settings>, oneshot::Sender<()>),
+}
+
+pub async fn bind(path: &Path) -> Result<UnixListener, Error> {
+    // The socket gives full control over the server, so it's restricted to its owner. It's created in a private
+    // directory and only moved into place afterwards, so that nobody can connect before that. Moving it also
+    // replaces a socket left behind by a previous instance.
+    let name = path
+        .file_name()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid control socket path"))?;
+    let directory = path.with_file_name(format!(".{}.{}", name.to_string_lossy(), process::id()));
+
+    // Left behind by a previous instance that happened to have the same PID.
+    match fs::remove_dir_all(&directory).await {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+
+    DirBuilder::new().mode(0o700).create(&directory).await?;
+
+    let result = async {
+        let temporary = directory.join("socket");
+        let listener = UnixListener::bind(&temporary)?;
+
+        fs::set_permissions(&temporary, Permissions::from_mode(0o600)).await?;
+        fs::rename(&temporary, path).await?;
+
+        Ok(listener)
+    }
+    .await;
+
+    fs::remove_dir_all(&directory).await?;
+    result
+}
+
+// Serves every connection on its own, until the server is gone.
+pub async fn listen(
+    listener: UnixListener,
+    commands: Sender<Command>,
+    loader: Option<Arc<Loader>>,
+) {
+    loop {
+        let result = tokio::select! {
+            result = listener.accept() => result,
+            _ = commands.closed() => return,
+        };
+
+        let stream = match result {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                tracing::error!("Stopped accepting control connections: {}", err);
+                return;
+            }
+        };
+
+        let commands = commands.clone();
+        let loader = loader.clone();
+        tokio::spawn(async move {
+            if let Err(err) = serve(stream, commands, loader.as_deref()).await {
+                tracing::warn!("Control connection error: {}", err);
+            }
+        });
+    }
+}
+
+async fn serve(
+    stream: UnixStream,
+    commands: Sender<Command>,
+    loader: Option<&Loader>,
+) -> Result<(), Error> {
+    let mut stream = BufStream::new(stream);
+
+    loop {
+        let request = match Request::decode(&mut stream).await {
+            Ok(request) => request,
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        tracing::debug!(request = ?request, "Received control request");
+
+        // Loading the configuration can take a while, so it's done here instead of in the server task.
+        let response = match request {
+            Request::Reload => match handle::reload(loader, &commands).await {
+                Ok(()) => Response::Done,
+                Err(err) => Response::Error(err.to_string()),
+            },
+            request => {
+                let (sender, receiver) = oneshot::channel();
+                if commands
+                    .send(Command::Request(request, sender))
+                    .await
+                    .is_err()
+                {
+                    return Ok(());
+                }
+
+                match receiver.await {
+                    Ok(response) => response,
+                    Err(_) => return Ok(()),
+                }
+            }
+        };
+
+        response.encode(&mut stream).await?;
+        stream.flush().await?;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    #[tokio::test]
+    async fn binds_private_socket() {
+        let path = env::temp_dir().join(format!("rkvm-control-{}.sock", process::id()));
+
+        // Binding again replaces the previous socket.
+        drop(bind(&path).await.unwrap());
+        let listener = bind(&path).await.unwrap();
+
+        let metadata = fs::metadata(&path).await.unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert!(UnixStream::connect(&path).await.is_ok());
+        assert!(listener.accept().await.is_ok());
+
+        let directory = path.with_file_name(format!(
+            ".rkvm-control-{}.sock.{}",
+            process::id(),
+            process::id()
+        ));
+        assert!(!directory.exists());
+
+        fs::remove_file(&path).await.unwrap();
+    }
+}
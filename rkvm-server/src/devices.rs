@@ -0,0 +1,142 @@
+use rkvm_input::interceptor::{Capability, Properties};
+use std::collections::HashSet;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Action {
+    // The device is not grabbed at all.
+    Ignore,
+    // The device is grabbed, but its input always stays on the server.
+    LocalOnly,
+    Forward,
+    // The device always drives the named target regardless of the focus and only exists on it.
+    Pin(String),
+}
+
+// All criteria that are set have to match for the rule to apply.
+#[derive(Clone)]
+pub struct Rule {
+    pub name: Option<String>,
+    pub vendor: Option<u16>,
+    pub product: Option<u16>,
+    pub phys: Option<String>,
+    pub capabilities: HashSet<Capability>,
+    pub action: Action,
+}
+
+impl Rule {
+    fn matches(&self, description: &Description) -> bool {
+        self.name
+            .as_ref()
+            .is_none_or(|pattern| glob(pattern, &description.name))
+            && self
+                .vendor
+                .is_none_or(|vendor| vendor == description.vendor)
+            && self
+                .product
+                .is_none_or(|product| product == description.product)
+            && self.phys.as_ref().is_none_or(|pattern| {
+                description
+                    .phys
+                    .as_ref()
+                    .is_some_and(|phys| glob(pattern, phys))
+            })
+            && self.capabilities.is_subset(&description.capabilities)
+    }
+}
+
+// What rules are matched against, kept for as long as the device exists so that it can be matched again
+// once the rules change.
+pub struct Description {
+    pub name: String,
+    pub vendor: u16,
+    pub product: u16,
+    pub phys: Option<String>,
+    pub capabilities: HashSet<Capability>,
+}
+
+impl Description {
+    pub fn new(properties: &Properties) -> Self {
+        let capabilities = [
+            Capability::Rel,
+            Capability::Abs,
+            Capability::Key,
+            Capability::Led,
+        ]
+        .into_iter()
+        .filter(|capability| properties.has(*capability))
+        .collect();
+
+        Self {
+            name: properties.name().to_string_lossy().into_owned(),
+            vendor: properties.vendor(),
+            product: properties.product(),
+            phys: properties
+                .phys()
+                .map(|phys| phys.to_string_lossy().into_owned()),
+            capabilities,
+        }
+    }
+}
+
+// The first matching rule decides, devices no rule matches are forwarded.
+pub struct Rules(pub Vec<Rule>);
+
+impl Rules {
+    pub fn action(&self, description: &Description) -> Action {
+        self.0
+            .iter()
+            .find(|rule| rule.matches(description))
+            .map_or(Action::Forward, |rule| rule.action.clone())
+    }
+}
+
+// Matches a pattern where * stands for any number of characters and ? for exactly one.
+fn glob(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let value = value.chars().collect::<Vec<_>>();
+
+    // Position in the pattern after the last star along with the position in the value it's matched to.
+    let mut star = None;
+    let (mut p, mut v) = (0, 0);
+
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, v));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match star {
+                // Let the star consume one more character.
+                Some((after, matched)) => {
+                    star = Some((after, matched + 1));
+                    p = after;
+                    v = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn glob_matches() {
+        assert!(glob("Yubico YubiKey*", "Yubico YubiKey OTP+FIDO+CCID"));
+        assert!(glob("*Power Button", "Power Button"));
+        assert!(glob("usb-*/input?", "usb-0000:00:14.0-2/input1"));
+        assert!(glob("*", ""));
+
+        assert!(!glob("Power Button", "Sleep Button"));
+        assert!(!glob("usb-*/input?", "usb-0000:00:14.0-2/input10"));
+        assert!(!glob("a*b", "ab c"));
+    }
+}
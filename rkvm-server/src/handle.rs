@@ -0,0 +1,221 @@
+use crate::builder::{Builder, Loader};
+use crate::control::{self, Command};
+use crate::recorder::Recorder;
+use crate::server::{self, Connection, Error, Settings};
+
+use rkvm_net::clipboard::Clipboard;
+use rkvm_net::control::{ClientInfo, DeviceInfo, Request, Response};
+use std::panic;
+use std::sync::Arc;
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::{broadcast, oneshot};
+use tokio::task::JoinHandle;
+
+// Number of focus changes kept for subscribers that haven't received them yet.
+const FOCUS_CAPACITY: usize = 16;
+
+// A running server, dropping it shuts the server down without waiting for it.
+pub struct Server {
+    commands: Sender<Command>,
+    loader: Option<Arc<Loader>>,
+    focus: broadcast::Sender<String>,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<Result<(), Error>>>,
+}
+
+impl Server {
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    pub(crate) fn start(
+        settings: Settings,
+        clipboard: Option<Clipboard>,
+        recorder: Option<Recorder>,
+        listeners: Vec<TcpListener>,
+        control_listener: Option<UnixListener>,
+        loader: Option<Arc<Loader>>,
+    ) -> Self {
+        let (connections_sender, connections_receiver) = mpsc::channel(1);
+        for listener in listeners {
+            tokio::spawn(accept(listener, connections_sender.clone()));
+        }
+
+        // Requests from the control socket are handled the same way as those made using the handle.
+        let (commands_sender, commands_receiver) = mpsc::channel(1);
+        if let Some(listener) = control_listener {
+            tokio::spawn(control::listen(
+                listener,
+                commands_sender.clone(),
+                loader.clone(),
+            ));
+        }
+
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+        let (focus_sender, _) = broadcast::channel(FOCUS_CAPACITY);
+
+        let task = tokio::spawn(server::run(
+            settings,
+            clipboard,
+            recorder,
+            connections_receiver,
+            commands_receiver,
+            shutdown_receiver,
+            focus_sender.clone(),
+        ));
+
+        Self {
+            commands: commands_sender,
+            loader,
+            focus: focus_sender,
+            shutdown: Some(shutdown_sender),
+            task: Some(task),
+        }
+    }
+
+    pub async fn clients(&self) -> Result<Vec<ClientInfo>, Error> {
+        match self.request(Request::Clients).await? {
+            Response::Clients(clients) => Ok(clients),
+            _ => Err(Error::UnexpectedResponse),
+        }
+    }
+
+    pub async fn devices(&self) -> Result<Vec<DeviceInfo>, Error> {
+        match self.request(Request::Devices).await? {
+            Response::Devices(devices) => Ok(devices),
+            _ => Err(Error::UnexpectedResponse),
+        }
+    }
+
+    // Returns the name of the focused target.
+    pub async fn focus(&self) -> Result<String, Error> {
+        match self.request(Request::Focus).await? {
+            Response::Focus(target) => Ok(target),
+            _ => Err(Error::UnexpectedResponse),
+        }
+    }
+
+    pub async fn switch(&self, target: &str) -> Result<(), Error> {
+        self.request(Request::Switch {
+            target: target.to_owned(),
+        })
+        .await
+        .map(|_| ())
+    }
+
+    pub async fn disconnect(&self, name: &str) -> Result<(), Error> {
+        self.request(Request::Disconnect {
+            name: name.to_owned(),
+        })
+        .await
+        .map(|_| ())
+    }
+
+    // Loads the configuration using the loader given to the builder and applies it.
+    pub async fn reload(&self) -> Result<(), Error> {
+        reload(self.loader.as_deref(), &self.commands).await
+    }
+
+    // Applies a new configuration, keeping the current one if it's invalid. Parts that can't be changed
+    // without restarting the server are ignored.
+    pub async fn reconfigure(&self, builder: Builder) -> Result<(), Error> {
+        reconfigure(builder, &self.commands).await
+    }
+
+    // Receives the name of every newly focused target from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.focus.subscribe()
+    }
+
+    // Waits until the server stops on its own, which only happens on errors. This function is cancel safe.
+    pub async fn wait(&mut self) -> Result<(), Error> {
+        let result = match &mut self.task {
+            Some(task) => join(task).await,
+            None => return Err(Error::Stopped),
+        };
+
+        self.task = None;
+        result
+    }
+
+    // Stops the server, returning once all devices have been released.
+    pub async fn shutdown(mut self) -> Result<(), Error> {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+
+        match &mut self.task {
+            Some(task) => join(task).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn request(&self, request: Request) -> Result<Response, Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.commands
+            .send(Command::Request(request, sender))
+            .await
+            .map_err(|_| Error::Stopped)?;
+
+        match receiver.await.map_err(|_| Error::Stopped)? {
+            Response::Error(err) => Err(Error::Request(err)),
+            response => Ok(response),
+        }
+    }
+}
+
+// Errors are logged here, since reloads can also be triggered from the control socket.
+pub(crate) async fn reload(
+    loader: Option<&Loader>,
+    commands: &Sender<Command>,
+) -> Result<(), Error> {
+    let result = async {
+        let loader = loader.ok_or(Error::NoLoader)?;
+        let builder = loader().await.map_err(Error::Load)?;
+
+        reconfigure(builder, commands).await
+    }
+    .await;
+
+    if let Err(err) = &result {
+        tracing::error!("Error reloading configuration: {}", err);
+    }
+
+    result
+}
+
+async fn reconfigure(mut builder: Builder, commands: &Sender<Command>) -> Result<(), Error> {
+    let settings = builder.settings().await?;
+
+    let (sender, receiver) = oneshot::channel();
+    commands
+        .send(Command::Reload(Box::new(settings), sender))
+        .await
+        .map_err(|_| Error::Stopped)?;
+
+    receiver.await.map_err(|_| Error::Stopped)
+}
+
+async fn join(task: &mut JoinHandle<Result<(), Error>>) -> Result<(), Error> {
+    match task.await {
+        Ok(result) => result,
+        Err(err) if err.is_panic() => panic::resume_unwind(err.into_panic()),
+        Err(_) => Err(Error::Stopped),
+    }
+}
+
+// Hands connections over to the server task, stopping on the first error.
+async fn accept(listener: TcpListener, connections: Sender<Connection>) {
+    loop {
+        let result = tokio::select! {
+            result = listener.accept() => result,
+            _ = connections.closed() => return,
+        };
+
+        let failed = result.is_err();
+        if connections.send(result).await.is_err() || failed {
+            return;
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use rkvm_input::key::Key;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+#[derive(Clone)]
+pub struct Screen {
+    pub width: i32,
+    pub height: i32,
+    pub neighbours: HashMap<Edge, String>,
+}
+
+// Tracks a virtual cursor across the screens of all targets.
+pub struct Layout {
+    screens: HashMap<String, Screen>,
+    // While all of these are held, the cursor can't leave the current screen.
+    lock_keys: HashSet<Key>,
+    pressed: HashSet<Key>,
+    // The target the cursor is currently on along with its position, which keeps fractions of pixels
+    // the target carries over between motion events.
+    target: Option<String>,
+    x: f64,
+    y: f64,
+}
+
+impl Layout {
+    pub fn new(screens: HashMap<String, Screen>, lock_keys: HashSet<Key>) -> Self {
+        Self {
+            screens,
+            lock_keys,
+            pressed: HashSet::new(),
+            target: None,
+            x: 0.0,
+            y: 0.0,
+        }
+    }
+
+    pub fn key(&mut self, key: Key, down: bool) {
+        if !self.lock_keys.contains(&key) {
+            return;
+        }
+
+        match down {
+            true => self.pressed.insert(key),
+            false => self.pressed.remove(&key),
+        };
+    }
+
+    // Returns where the pointer of a newly focused target has to be placed, if the target has a screen.
+    //
+    // If the cursor was not on the target before, it's placed in the middle of its screen,
+    // since the focus was changed by other means than crossing an edge.
+    pub fn enter(&mut self, target: &str) -> Option<(i32, i32)> {
+        let screen = self.screens.get(target)?;
+
+        if self.target.as_deref() != Some(target) {
+            self.target = Some(target.to_owned());
+            self.x = f64::from(screen.width / 2);
+            self.y = f64::from(screen.height / 2);
+        }
+
+        Some((self.x as i32, self.y as i32))
+    }
+
+    // Moves the cursor on the screen of the given target and returns the neighbour it moved to, if any.
+    // The motion has to be scaled the same way as it is for the target.
+    pub fn motion<F: Fn(&str) -> bool>(
+        &mut self,
+        target: &str,
+        dx: f64,
+        dy: f64,
+        enter: F,
+    ) -> Option<String> {
+        self.enter(target)?;
+        let screen = &self.screens[target];
+
+        let x = self.x + dx;
+        let y = self.y + dy;
+
+        let edge = if x < 0.0 {
+            Some(Edge::Left)
+        } else if x >= f64::from(screen.width) {
+            Some(Edge::Right)
+        } else if y < 0.0 {
+            Some(Edge::Top)
+        } else if y >= f64::from(screen.height) {
+            Some(Edge::Bottom)
+        } else {
+            None
+        };
+
+        let locked = !self.lock_keys.is_empty() && self.pressed == self.lock_keys;
+        let crossed = edge
+            .filter(|_| !locked)
+            .and_then(|edge| screen.neighbours.get(&edge).map(|name| (edge, name)))
+            .and_then(|(edge, name)| {
+                let neighbour = self.screens.get(name)?;
+                if !enter(name) {
+                    return None;
+                }
+
+                // Enter the neighbour at the opposite edge, keeping the relative position along it.
+                let (x, y) = match edge {
+                    Edge::Left => (
+                        f64::from(neighbour.width - 1),
+                        scale(y, screen.height, neighbour.height),
+                    ),
+                    Edge::Right => (0.0, scale(y, screen.height, neighbour.height)),
+                    Edge::Top => (
+                        scale(x, screen.width, neighbour.width),
+                        f64::from(neighbour.height - 1),
+                    ),
+                    Edge::Bottom => (scale(x, screen.width, neighbour.width), 0.0),
+                };
+
+                Some((name.clone(), x, y))
+            });
+
+        match crossed {
+            Some((name, x, y)) => {
+                self.target = Some(name.clone());
+                self.x = x;
+                self.y = y;
+
+                Some(name)
+            }
+            None => {
+                self.x = x.clamp(0.0, f64::from(screen.width - 1));
+                self.y = y.clamp(0.0, f64::from(screen.height - 1));
+
+                None
+            }
+        }
+    }
+}
+
+fn scale(value: f64, from: i32, to: i32) -> f64 {
+    (value.clamp(0.0, f64::from(from - 1)) * f64::from(to) / f64::from(from)).floor()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rkvm_input::key::Keyboard;
+
+    fn layout() -> Layout {
+        let screens = [
+            (
+                "local".to_owned(),
+                Screen {
+                    width: 1920,
+                    height: 1080,
+                    neighbours: [(Edge::Right, "laptop".to_owned())].into(),
+                },
+            ),
+            (
+                "laptop".to_owned(),
+                Screen {
+                    width: 1280,
+                    height: 720,
+                    neighbours: [(Edge::Left, "local".to_owned())].into(),
+                },
+            ),
+        ];
+
+        Layout::new(screens.into(), [Key::Key(Keyboard::ScrollLock)].into())
+    }
+
+    #[test]
+    fn starts_in_the_middle() {
+        let mut layout = layout();
+
+        assert_eq!(layout.motion("local", 0.0, 0.0, |_| true), None);
+        assert_eq!((layout.x, layout.y), (960.0, 540.0));
+    }
+
+    #[test]
+    fn crosses_to_neighbour() {
+        let mut layout = layout();
+
+        assert_eq!(layout.motion("local", 959.0, 0.0, |_| true), None);
+        assert_eq!(
+            layout.motion("local", 1.0, 0.0, |_| true),
+            Some("laptop".to_owned())
+        );
+        assert_eq!((layout.x, layout.y), (0.0, 360.0));
+
+        assert_eq!(
+            layout.motion("laptop", -1.0, 0.0, |_| true),
+            Some("local".to_owned())
+        );
+        assert_eq!((layout.x, layout.y), (1919.0, 540.0));
+    }
+
+    #[test]
+    fn clamps_without_neighbour() {
+        let mut layout = layout();
+
+        assert_eq!(layout.motion("local", 0.0, -10000.0, |_| true), None);
+        assert_eq!((layout.x, layout.y), (960.0, 0.0));
+    }
+
+    #[test]
+    fn clamps_when_locked_or_unavailable() {
+        let mut layout = layout();
+
+        layout.key(Key::Key(Keyboard::ScrollLock), true);
+        assert_eq!(layout.motion("local", 10000.0, 0.0, |_| true), None);
+        assert_eq!((layout.x, layout.y), (1919.0, 540.0));
+
+        layout.key(Key::Key(Keyboard::ScrollLock), false);
+
+        assert_eq!(layout.motion("local", 1.0, 0.0, |_| false), None);
+        assert_eq!((layout.x, layout.y), (1919.0, 540.0));
+    }
+
+    #[test]
+    fn recenters_after_external_switch() {
+        let mut layout = layout();
+
+        layout.motion("local", 100.0, 100.0, |_| true);
+        assert_eq!(layout.enter("laptop"), Some((640, 360)));
+    }
+
+    #[test]
+    fn keeps_fractions() {
+        let mut layout = layout();
+
+        for _ in 0..5 {
+            layout.motion("local", 0.5, 0.0, |_| true);
+        }
+
+        assert_eq!(layout.enter("local"), Some((962, 540)));
+    }
+
+    #[test]
+    fn enters_where_the_edge_was_crossed() {
+        let mut layout = layout();
+
+        layout.motion("local", 960.0, 270.0, |_| true);
+        assert_eq!(layout.enter("laptop"), Some((0, 540)));
+        assert_eq!(layout.enter("unknown"), None);
+    }
+}
@@ -0,0 +1,19 @@
+mod builder;
+mod control;
+mod devices;
+mod handle;
+mod layout;
+mod pointer;
+mod queue;
+mod recorder;
+mod remap;
+mod server;
+mod tls;
+
+pub use builder::{Builder, ConfigError, Target};
+pub use devices::{Action, Rule};
+pub use handle::Server;
+pub use layout::{Edge, Screen};
+pub use pointer::Settings as PointerSettings;
+pub use rkvm_net::control::{ClientInfo, DeviceInfo};
+pub use server::{Error, LOCAL_TARGET};
@@ -1,13 +1,15 @@
 mod config;
-mod server;
-mod tls;
 
 use clap::Parser;
 use config::Config;
+use rkvm_server::{Action, Builder, Rule, Server};
 use std::future;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 use std::time::Duration;
+use thiserror::Error;
+use tokio::signal::unix::{self, SignalKind};
 use tokio::{fs, signal, time};
 use tracing::subscriber;
 use tracing_subscriber::filter::{EnvFilter, LevelFilter};
@@ -21,6 +23,8 @@ struct Args {
     config_path: PathBuf,
     #[structopt(help = "Shutdown after N seconds", long, short)]
     shutdown_after: Option<u64>,
+    #[structopt(help = "Record everything the devices do into a file", long)]
+    record: Option<PathBuf>,
 }
 
 #[tokio::main]
@@ -36,26 +40,28 @@ async fn main() -> ExitCode {
     subscriber::set_global_default(registry).unwrap();
 
     let args = Args::parse();
-    let config = match fs::read_to_string(&args.config_path).await {
-        Ok(config) => config,
+    let mut builder = match load(&args.config_path).await {
+        Ok(builder) => builder,
         Err(err) => {
-            tracing::error!("Error reading config: {}", err);
+            tracing::error!("{}", err);
             return ExitCode::FAILURE;
         }
     };
 
-    let config = match toml::from_str::<Config>(&config) {
-        Ok(config) => config,
-        Err(err) => {
-            tracing::error!("Error parsing config: {}", err);
-            return ExitCode::FAILURE;
-        }
-    };
+    if let Some(path) = args.record {
+        builder = builder.record(path);
+    }
 
-    let acceptor = match tls::configure(&config.certificate, &config.key).await {
-        Ok(acceptor) => acceptor,
+    let config_path = args.config_path.clone();
+    builder = builder.loader(move || {
+        let config_path = config_path.clone();
+        async move { load(&config_path).await }
+    });
+
+    let mut server = match builder.build().await {
+        Ok(server) => server,
         Err(err) => {
-            tracing::error!("Error configuring TLS: {}", err);
+            tracing::error!("Error: {}", err);
             return ExitCode::FAILURE;
         }
     };
@@ -66,30 +72,121 @@ async fn main() -> ExitCode {
             None => future::pending().await,
         }
     };
+    tokio::pin!(shutdown);
+
+    let mut hangup = match unix::signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            tracing::error!("Error setting up signal handler: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
 
-    let switch_keys = config.switch_keys.into_iter().map(Into::into).collect();
-    let propagate_switch_keys = config.propagate_switch_keys.unwrap_or(true);
+    loop {
+        tokio::select! {
+            result = server.wait() => {
+                if let Err(err) = result {
+                    tracing::error!("Error: {}", err);
+                    return ExitCode::FAILURE;
+                }
 
-    tokio::select! {
-        result = server::run(config.listen, acceptor, &config.password, &switch_keys, propagate_switch_keys) => {
-            if let Err(err) = result {
-                tracing::error!("Error: {}", err);
-                return ExitCode::FAILURE;
+                break;
             }
-        }
-        // This is needed to properly clean libevdev stuff up.
-        result = signal::ctrl_c() => {
-            if let Err(err) = result {
-                tracing::error!("Error setting up signal handler: {}", err);
-                return ExitCode::FAILURE;
+            result = signal::ctrl_c() => {
+                if let Err(err) = result {
+                    tracing::error!("Error setting up signal handler: {}", err);
+                    return ExitCode::FAILURE;
+                }
+
+                tracing::info!("Exiting on signal");
+                break;
             }
+            _ = hangup.recv() => {
+                tracing::info!("Reloading configuration on signal");
 
-            tracing::info!("Exiting on signal");
-        }
-        _ = shutdown => {
-            tracing::info!("Shutting down as requested");
+                // Errors are logged by the server, which keeps running with the previous configuration.
+                let _ = server.reload().await;
+            }
+            _ = &mut shutdown => {
+                tracing::info!("Shutting down as requested");
+                break;
+            }
         }
     }
 
+    // This is needed to properly clean libevdev stuff up.
+    if let Err(err) = server.shutdown().await {
+        tracing::error!("Error: {}", err);
+        return ExitCode::FAILURE;
+    }
+
     ExitCode::SUCCESS
 }
+
+#[derive(Error, Debug)]
+enum LoadError {
+    #[error("Error reading config: {0}")]
+    Read(io::Error),
+    #[error("Error parsing config: {0}")]
+    Parse(toml::de::Error),
+    #[error("Devices can only be pinned to target {0:?} with the forward action")]
+    Pin(String),
+}
+
+// Reads the configuration into a builder, used both on startup and when reloading.
+async fn load(path: &Path) -> Result<Builder, LoadError> {
+    let config = fs::read_to_string(path).await.map_err(LoadError::Read)?;
+    let config = toml::from_str::<Config>(&config).map_err(LoadError::Parse)?;
+
+    let mut builder = Server::builder()
+        .listen(config.listen)
+        .certificate(config.certificate, config.key)
+        .switch_keys(config.switch_keys.into_iter().map(Into::into))
+        .propagate_switch_keys(config.propagate_switch_keys.unwrap_or(true))
+        .edge_lock_keys(config.edge_lock_keys.into_iter().map(Into::into));
+
+    if let Some(password) = config.password {
+        builder = builder.password(password);
+    }
+
+    if let Some(path) = config.client_ca {
+        builder = builder.client_ca(path);
+    }
+
+    for path in config.client_certificates {
+        builder = builder.client_certificate(path);
+    }
+
+    for target in config.targets {
+        builder = builder.target(target.into());
+    }
+
+    for device in config.devices {
+        let action = match (device.action, device.target) {
+            (config::Action::Ignore, None) => Action::Ignore,
+            (config::Action::LocalOnly, None) => Action::LocalOnly,
+            (config::Action::Forward, None) => Action::Forward,
+            (config::Action::Forward, Some(target)) => Action::Pin(target),
+            (_, Some(target)) => return Err(LoadError::Pin(target)),
+        };
+
+        builder = builder.device(Rule {
+            name: device.name,
+            vendor: device.vendor,
+            product: device.product,
+            phys: device.phys,
+            capabilities: device.capabilities.into_iter().map(Into::into).collect(),
+            action,
+        });
+    }
+
+    if let Some(backend) = config.clipboard {
+        builder = builder.clipboard(backend.into());
+    }
+
+    if let Some(path) = config.control_socket {
+        builder = builder.control_socket(path);
+    }
+
+    Ok(builder)
+}
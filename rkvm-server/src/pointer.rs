@@ -0,0 +1,134 @@
+use rkvm_input::event::Event;
+use rkvm_input::rel::{RelAxis, RelEvent};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy)]
+pub struct Settings {
+    // Multiplier of pointer motion.
+    pub speed: f64,
+    // How much faster the pointer gets per unit of motion in a single event, zero meaning no acceleration.
+    pub acceleration: f64,
+    // Multiplier of wheel motion, including high resolution wheel events.
+    pub wheel_speed: f64,
+    pub natural_scroll: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            acceleration: 0.0,
+            wheel_speed: 1.0,
+            natural_scroll: false,
+        }
+    }
+}
+
+// Scales relative motion sent to a target, carrying sub-unit remainders over to the following events.
+#[derive(Default)]
+pub struct Pointer {
+    settings: Settings,
+    remainders: HashMap<RelAxis, f64>,
+}
+
+impl Pointer {
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            settings,
+            remainders: HashMap::new(),
+        }
+    }
+
+    // Returns None if the motion is too small to be sent yet.
+    pub fn apply(&mut self, event: Event) -> Option<Event> {
+        let (axis, value) = match event {
+            Event::Rel(RelEvent { axis, value }) => (axis, value),
+            event => return Some(event),
+        };
+
+        let value = match self.scale(axis, value) {
+            Some(value) => value,
+            None => return Some(event),
+        };
+
+        let remainder = self.remainders.entry(axis).or_default();
+        let value = value + *remainder;
+        let whole = value.trunc();
+
+        *remainder = value - whole;
+
+        if whole == 0.0 {
+            return None;
+        }
+
+        Some(Event::Rel(RelEvent {
+            axis,
+            value: whole as i32,
+        }))
+    }
+
+    // Motion along an axis as the target gets it, without remainders. Returns None for axes that aren't scaled.
+    pub fn scale(&self, axis: RelAxis, value: i32) -> Option<f64> {
+        let value = f64::from(value);
+        let gain = match axis {
+            RelAxis::X | RelAxis::Y => {
+                self.settings.speed * (1.0 + self.settings.acceleration * value.abs())
+            }
+            RelAxis::Wheel | RelAxis::HWheel | RelAxis::WheelHiRes | RelAxis::HWheelHiRes => {
+                let direction = if self.settings.natural_scroll {
+                    -1.0
+                } else {
+                    1.0
+                };
+
+                self.settings.wheel_speed * direction
+            }
+            _ => return None,
+        };
+
+        Some(value * gain)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rel(pointer: &mut Pointer, axis: RelAxis, value: i32) -> Option<i32> {
+        match pointer.apply(Event::Rel(RelEvent { axis, value }))? {
+            Event::Rel(RelEvent { value, .. }) => Some(value),
+            event => panic!("Unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn carries_remainders() {
+        let mut pointer = Pointer::new(Settings {
+            speed: 0.4,
+            ..Default::default()
+        });
+
+        let moved = (0..5)
+            .filter_map(|_| rel(&mut pointer, RelAxis::X, 1))
+            .sum::<i32>();
+        assert_eq!(moved, 2);
+
+        // Each axis has its own remainder, so motion back and forth cancels out.
+        assert_eq!(rel(&mut pointer, RelAxis::Y, 2), None);
+        assert_eq!(rel(&mut pointer, RelAxis::Y, -2), None);
+    }
+
+    #[test]
+    fn inverts_scrolling() {
+        let mut pointer = Pointer::new(Settings {
+            speed: 2.0,
+            wheel_speed: 0.5,
+            natural_scroll: true,
+            ..Default::default()
+        });
+
+        assert_eq!(rel(&mut pointer, RelAxis::WheelHiRes, 120), Some(-60));
+        assert_eq!(rel(&mut pointer, RelAxis::X, 3), Some(6));
+        assert_eq!(rel(&mut pointer, RelAxis::Dial, 3), Some(3));
+    }
+}
@@ -0,0 +1,376 @@
+use rkvm_input::event::Event;
+use rkvm_input::rel::RelEvent;
+use rkvm_input::sync::SyncEvent;
+use rkvm_net::Update;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+// An entry of a queue, possibly carrying an input event of a device.
+pub trait Entry {
+    fn event(&self) -> Option<(usize, &Event)>;
+
+    fn event_mut(&mut self) -> Option<(usize, &mut Event)>;
+}
+
+impl Entry for Event {
+    // Each device has its own queue, so the ID doesn't matter.
+    fn event(&self) -> Option<(usize, &Event)> {
+        Some((0, self))
+    }
+
+    fn event_mut(&mut self) -> Option<(usize, &mut Event)> {
+        Some((0, self))
+    }
+}
+
+impl Entry for Update {
+    fn event(&self) -> Option<(usize, &Event)> {
+        match self {
+            Self::Event { id, event } => Some((*id, event)),
+            _ => None,
+        }
+    }
+
+    fn event_mut(&mut self) -> Option<(usize, &mut Event)> {
+        match self {
+            Self::Event { id, event } => Some((*id, event)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Closed;
+
+// Creates a bounded queue which never makes the producer wait.
+//
+// Once the queue is full, relative motion is added up with motion of the same axis that is still queued
+// and the oldest frames consisting only of motion are dropped. Everything else, such as key events or
+// device creation, is always delivered, in which case the queue can grow past its capacity.
+pub fn queue<T: Entry>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            entries: VecDeque::new(),
+            dropped: 0,
+            producer: true,
+            consumer: true,
+        }),
+        notify: Notify::new(),
+    });
+
+    let producer = Producer {
+        shared: shared.clone(),
+        capacity,
+    };
+
+    (producer, Consumer { shared })
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    notify: Notify,
+}
+
+struct State<T> {
+    // Entries along with the time they were pushed at.
+    entries: VecDeque<(Instant, T)>,
+    // Total number of events dropped.
+    dropped: u64,
+    producer: bool,
+    consumer: bool,
+}
+
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+    capacity: usize,
+}
+
+impl<T: Entry> Producer<T> {
+    // Pushes an entry, returning the number of events that had to be dropped to make room for it.
+    pub fn push(&self, mut entry: T) -> Result<usize, Closed> {
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.consumer {
+            return Err(Closed);
+        }
+
+        if state.entries.len() >= self.capacity {
+            if let Some((id, Event::Rel(event))) = entry.event_mut() {
+                if coalesce(&mut state.entries, id, event) {
+                    return Ok(0);
+                }
+            }
+        }
+
+        state.entries.push_back((Instant::now(), entry));
+
+        let mut dropped = 0;
+        while state.entries.len() > self.capacity {
+            match drop_frame(&mut state.entries) {
+                Some(count) => dropped += count,
+                None => break,
+            }
+        }
+
+        state.dropped += dropped as u64;
+        drop(state);
+
+        self.shared.notify.notify_one();
+
+        Ok(dropped)
+    }
+
+    // How long the oldest entry has been waiting for.
+    pub fn lag(&self) -> Duration {
+        match self.shared.state.lock().unwrap().entries.front() {
+            Some((time, _)) => time.elapsed(),
+            None => Duration::ZERO,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        !self.shared.state.lock().unwrap().consumer
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.shared.state.lock().unwrap().dropped
+    }
+}
+
+// Dropping the producer discards everything that hasn't been received yet.
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.producer = false;
+        state.entries.clear();
+        drop(state);
+
+        self.shared.notify.notify_one();
+    }
+}
+
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Consumer<T> {
+    // Returns None once the producer is gone.
+    //
+    // This is cancel safe.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if let Some((_, entry)) = state.entries.pop_front() {
+                    return Some(entry);
+                }
+
+                if !state.producer {
+                    return None;
+                }
+            }
+
+            // Notifications sent while nobody is waiting are stored, so none can be missed here.
+            self.shared.notify.notified().await;
+        }
+    }
+
+    // Returns an entry if one is available right away.
+    pub fn try_recv(&mut self) -> Option<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.entries.pop_front().map(|(_, entry)| entry)
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().consumer = false;
+    }
+}
+
+// Adds relative motion to the latest queued motion of the same device and axis.
+//
+// Does not look past key events, since moving motion before them would change their meaning
+// (for example, where a drag starts).
+fn coalesce<T: Entry>(entries: &mut VecDeque<(Instant, T)>, id: usize, event: &RelEvent) -> bool {
+    for (_, entry) in entries.iter_mut().rev() {
+        let (entry_id, entry_event) = match entry.event_mut() {
+            Some(event) => event,
+            None => return false,
+        };
+
+        if entry_id != id {
+            continue;
+        }
+
+        match entry_event {
+            Event::Rel(queued) if queued.axis == event.axis => {
+                return match queued.value.checked_add(event.value) {
+                    Some(value) => {
+                        queued.value = value;
+                        true
+                    }
+                    None => false,
+                };
+            }
+            Event::Key(_) => return false,
+            _ => {}
+        }
+    }
+
+    false
+}
+
+// Drops the oldest complete frame consisting only of motion and returns the number of its events.
+fn drop_frame<T: Entry>(entries: &mut VecDeque<(Instant, T)>) -> Option<usize> {
+    // Indices of the current frame of each device and whether it can be dropped.
+    let mut frames = HashMap::<usize, (Vec<usize>, bool)>::new();
+    let mut found = None;
+
+    for (i, (_, entry)) in entries.iter().enumerate() {
+        let (id, event) = match entry.event() {
+            Some(event) => event,
+            None => continue,
+        };
+
+        let (indices, droppable) = frames.entry(id).or_insert_with(|| (Vec::new(), true));
+        indices.push(i);
+
+        match event {
+            Event::Rel(_) | Event::Abs(_) | Event::Sync(SyncEvent::Mt) => {}
+            Event::Key(_) | Event::Led(_) => *droppable = false,
+            Event::Sync(SyncEvent::All) => {
+                let (indices, droppable) = frames.remove(&id).unwrap();
+                if droppable {
+                    found = Some(indices);
+                    break;
+                }
+            }
+        }
+    }
+
+    let indices = found?;
+    let count = indices.len();
+
+    let mut i = 0;
+    let mut indices = indices.into_iter().peekable();
+
+    entries.retain(|_| {
+        let keep = indices.peek() != Some(&i);
+        if !keep {
+            indices.next();
+        }
+
+        i += 1;
+        keep
+    });
+
+    Some(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rkvm_input::key::{Button, Key, KeyEvent};
+    use rkvm_input::rel::RelAxis;
+
+    fn rel(axis: RelAxis, value: i32) -> Event {
+        Event::Rel(RelEvent { axis, value })
+    }
+
+    fn key(down: bool) -> Event {
+        Event::Key(KeyEvent {
+            key: Key::Button(Button::Left),
+            down,
+        })
+    }
+
+    fn sync() -> Event {
+        Event::Sync(SyncEvent::All)
+    }
+
+    fn drain(consumer: &Consumer<Event>) -> Vec<Event> {
+        consumer
+            .shared
+            .state
+            .lock()
+            .unwrap()
+            .entries
+            .drain(..)
+            .map(|(_, event)| event)
+            .collect()
+    }
+
+    #[test]
+    fn coalesces_motion() {
+        let (producer, consumer) = queue(2);
+
+        producer.push(rel(RelAxis::X, 1)).unwrap();
+        producer.push(rel(RelAxis::Y, 2)).unwrap();
+        assert_eq!(producer.push(rel(RelAxis::X, 3)).unwrap(), 0);
+
+        let events = drain(&consumer);
+        assert!(matches!(
+            events[..],
+            [
+                Event::Rel(RelEvent {
+                    axis: RelAxis::X,
+                    value: 4
+                }),
+                Event::Rel(RelEvent {
+                    axis: RelAxis::Y,
+                    value: 2
+                })
+            ]
+        ));
+    }
+
+    #[test]
+    fn drops_oldest_motion_frame() {
+        let (producer, consumer) = queue(4);
+
+        producer.push(rel(RelAxis::X, 1)).unwrap();
+        producer.push(sync()).unwrap();
+        producer.push(key(true)).unwrap();
+        producer.push(sync()).unwrap();
+        assert_eq!(producer.push(rel(RelAxis::Y, 1)).unwrap(), 2);
+        assert_eq!(producer.dropped(), 2);
+
+        let events = drain(&consumer);
+        assert!(matches!(
+            events[..],
+            [Event::Key(_), Event::Sync(_), Event::Rel(_)]
+        ));
+    }
+
+    #[test]
+    fn keeps_key_events() {
+        let (producer, consumer) = queue(2);
+
+        for _ in 0..4 {
+            producer.push(key(true)).unwrap();
+            producer.push(key(false)).unwrap();
+            producer.push(sync()).unwrap();
+        }
+
+        assert_eq!(producer.dropped(), 0);
+        assert_eq!(drain(&consumer).len(), 12);
+    }
+
+    #[tokio::test]
+    async fn closes() {
+        let (producer, mut consumer) = queue(1);
+
+        producer.push(sync()).unwrap();
+        drop(producer);
+
+        assert!(consumer.recv().await.is_none());
+
+        let (producer, consumer) = queue::<Event>(1);
+        drop(consumer);
+
+        assert!(producer.is_closed());
+        assert!(producer.push(sync()).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use rkvm_net::message::Message;
+use rkvm_net::recording::{Header, Record};
+use rkvm_net::Update;
+use std::fs::Permissions;
+use std::io::Error;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Instant;
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+// Writes updates describing everything the server's devices do into a file on the side.
+pub struct Recorder {
+    sender: UnboundedSender<Record>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub async fn create(path: &Path) -> Result<Self, Error> {
+        // Recordings contain everything typed, including passwords, so only the owner may read them, including
+        // when overwriting an existing file.
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)
+            .await?;
+
+        file.set_permissions(Permissions::from_mode(0o600)).await?;
+
+        let mut file = BufWriter::new(file);
+        Header::CURRENT.encode(&mut file).await?;
+        file.flush().await?;
+
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Record>();
+        tokio::spawn(async move {
+            let result = async {
+                while let Some(record) = receiver.recv().await {
+                    record.encode(&mut file).await?;
+
+                    // Batch everything that queued up in the meantime into a single flush.
+                    while let Ok(record) = receiver.try_recv() {
+                        record.encode(&mut file).await?;
+                    }
+
+                    file.flush().await?;
+                }
+
+                Ok::<_, Error>(())
+            }
+            .await;
+
+            if let Err(err) = result {
+                tracing::error!("Stopped recording: {}", err);
+            }
+        });
+
+        Ok(Self {
+            sender,
+            start: Instant::now(),
+        })
+    }
+
+    // Never waits, the update is written by a separate task.
+    pub fn record(&self, update: Update) {
+        let record = Record {
+            elapsed: self.start.elapsed(),
+            update,
+        };
+
+        let _ = self.sender.send(record);
+    }
+}
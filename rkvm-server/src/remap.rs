@@ -0,0 +1,105 @@
+use rkvm_input::event::Event;
+use rkvm_input::key::{Key, KeyEvent};
+use std::collections::{HashMap, HashSet};
+use std::slice;
+
+// Replaces keys and buttons sent to a target by other keys or combinations of them.
+#[derive(Default, PartialEq)]
+pub struct Remap(pub HashMap<Key, Vec<Key>>);
+
+impl Remap {
+    // Keys a device has on the target, given the keys it physically has.
+    pub fn keys(&self, keys: &HashSet<Key>) -> HashSet<Key> {
+        keys.iter()
+            .flat_map(|key| match self.0.get(key) {
+                Some(keys) => keys.as_slice(),
+                None => slice::from_ref(key),
+            })
+            .copied()
+            .collect()
+    }
+
+    // Combinations are pressed in order and released in reverse.
+    pub fn apply(&self, event: Event) -> impl Iterator<Item = Event> + '_ {
+        let (event, keys, down) = match event {
+            Event::Key(KeyEvent { key, down }) => match self.0.get(&key) {
+                Some(keys) => (None, keys.as_slice(), down),
+                None => (Some(event), [].as_slice(), down),
+            },
+            event => (Some(event), [].as_slice(), false),
+        };
+
+        let len = keys.len();
+        let keys = (0..len).map(move |i| {
+            let key = if down { keys[i] } else { keys[len - 1 - i] };
+            Event::Key(KeyEvent { key, down })
+        });
+
+        event.into_iter().chain(keys)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rkvm_input::key::Keyboard;
+    use rkvm_input::sync::SyncEvent;
+
+    fn keys(events: impl Iterator<Item = Event>) -> Vec<(Key, bool)> {
+        events
+            .map(|event| match event {
+                Event::Key(KeyEvent { key, down }) => (key, down),
+                event => panic!("Unexpected event {:?}", event),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn remaps_combinations() {
+        let ctrl = Key::Key(Keyboard::LeftCtrl);
+        let alt = Key::Key(Keyboard::LeftAlt);
+        let t = Key::Key(Keyboard::T);
+        let esc = Key::Key(Keyboard::Esc);
+        let caps_lock = Key::Key(Keyboard::CapsLock);
+        let f13 = Key::Key(Keyboard::F13);
+
+        let remap = Remap(HashMap::from([
+            (caps_lock, vec![esc]),
+            (f13, vec![ctrl, alt, t]),
+        ]));
+
+        let press = Event::Key(KeyEvent {
+            key: f13,
+            down: true,
+        });
+        assert_eq!(
+            keys(remap.apply(press)),
+            [(ctrl, true), (alt, true), (t, true)]
+        );
+
+        let release = Event::Key(KeyEvent {
+            key: f13,
+            down: false,
+        });
+        assert_eq!(
+            keys(remap.apply(release)),
+            [(t, false), (alt, false), (ctrl, false)]
+        );
+
+        let unmapped = Event::Key(KeyEvent {
+            key: esc,
+            down: true,
+        });
+        assert_eq!(keys(remap.apply(unmapped)), [(esc, true)]);
+
+        assert!(matches!(
+            remap.apply(Event::Sync(SyncEvent::All)).collect::<Vec<_>>()[..],
+            [Event::Sync(SyncEvent::All)]
+        ));
+
+        assert_eq!(
+            remap.keys(&HashSet::from([caps_lock, f13, t])),
+            HashSet::from([esc, ctrl, alt, t])
+        );
+    }
+}
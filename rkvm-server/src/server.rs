@@ -1,27 +1,50 @@
+use crate::builder::ConfigError;
+use crate::control::Command;
+use crate::devices::{Action, Description, Rules};
+use crate::layout::Layout;
+use crate::pointer::{self, Pointer};
+use crate::queue::{self, Consumer, Producer};
+use crate::recorder::Recorder;
+use crate::remap::Remap;
+use crate::tls;
+
 use rkvm_input::abs::{AbsAxis, AbsInfo};
 use rkvm_input::event::Event;
 use rkvm_input::key::{Key, KeyEvent};
+use rkvm_input::led::{Led, LedEvent};
 use rkvm_input::monitor::Monitor;
-use rkvm_input::rel::RelAxis;
+use rkvm_input::rel::{RelAxis, RelEvent};
 use rkvm_input::sync::SyncEvent;
 use rkvm_net::auth::{AuthChallenge, AuthResponse, AuthStatus};
+use rkvm_net::clipboard::Clipboard;
+use rkvm_net::control::{ClientInfo, DeviceInfo, Request, Response};
 use rkvm_net::message::Message;
-use rkvm_net::version::Version;
-use rkvm_net::{Pong, Update};
+use rkvm_net::version::{Capabilities, Handshake, Negotiated, Version};
+use rkvm_net::{Hello, Pong, Report, Update};
 use slab::Slab;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error;
 use std::ffi::CString;
+use std::fmt::{self, Display, Formatter};
+use std::future;
 use std::io::{self, ErrorKind};
+use std::mem;
 use std::net::SocketAddr;
-use std::time::Instant;
+use std::ops::Bound;
+use std::slice;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tokio::io::{AsyncWriteExt, BufStream};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc::error::TrySendError;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
 use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::{broadcast, oneshot};
 use tokio::time;
 use tokio_rustls::TlsAcceptor;
-use tracing::Instrument;
+use tracing::{field, Instrument, Span};
+
+// Name of the target representing the server itself.
+pub const LOCAL_TARGET: &str = "local";
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -29,70 +52,180 @@ pub enum Error {
     Network(io::Error),
     #[error("Input error: {0}")]
     Input(io::Error),
-    #[error("Event queue overflow")]
-    Overflow,
+    #[error("Control socket error: {0}")]
+    Control(io::Error),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("Error configuring TLS: {0}")]
+    Tls(tls::Error),
+    #[error("Error reading clipboard: {0}")]
+    Clipboard(io::Error),
+    #[error("Error creating recording: {0}")]
+    Recording(io::Error),
+    #[error("{0}")]
+    Request(String),
+    #[error(transparent)]
+    Load(Box<dyn error::Error + Send + Sync>),
+    #[error("Reloading the configuration is not supported")]
+    NoLoader,
+    #[error("Unexpected response from the server task")]
+    UnexpectedResponse,
+    #[error("Server stopped")]
+    Stopped,
+}
+
+// Number of events queued for a device or a client before motion starts being coalesced and dropped.
+const QUEUE_CAPACITY: usize = 32;
+
+// How long an update can wait in the queue of a client before the client is disconnected.
+const MAX_LAG: Duration = Duration::from_secs(2);
+
+// How far the pointer is moved to get it into the corner of a screen, regardless of its size.
+const WARP_DISTANCE: i32 = 32767;
+
+// How input sent to a client is adjusted.
+#[derive(Clone, Default)]
+pub struct Profile {
+    pub remap: Arc<Remap>,
+    pub pointer: pointer::Settings,
+}
+
+// Decides when the focus moves between targets.
+pub struct Switching {
+    // Keys cycling through all targets.
+    pub switch_keys: HashSet<Key>,
+    // Keys focusing a specific target.
+    pub targets: HashMap<String, HashSet<Key>>,
+    // Broadcast targets along with the clients they mirror input to.
+    pub groups: HashMap<String, Arc<[String]>>,
+    pub propagate_switch_keys: bool,
+    pub layout: Layout,
 }
 
+// Settings validated by the builder.
+pub struct Settings {
+    pub switching: Switching,
+    pub rules: Rules,
+    // Profiles of clients, by their name.
+    pub profiles: HashMap<String, Profile>,
+    pub password: Option<String>,
+    pub acceptor: TlsAcceptor,
+}
+
+pub type Connection = Result<(TcpStream, SocketAddr), io::Error>;
+
 pub async fn run(
-    listen: SocketAddr,
-    acceptor: TlsAcceptor,
-    password: &str,
-    switch_keys: &HashSet<Key>,
-    propagate_switch_keys: bool,
+    settings: Settings,
+    // Clipboard of the server, contents are shared only if it's set.
+    mut clipboard: Option<Clipboard>,
+    // Records everything the devices do, regardless of where it's sent.
+    recorder: Option<Recorder>,
+    mut connections: Receiver<Connection>,
+    mut commands: Receiver<Command>,
+    mut shutdown: oneshot::Receiver<()>,
+    // Receives the name of every newly focused target.
+    focus: broadcast::Sender<String>,
 ) -> Result<(), Error> {
-    let listener = TcpListener::bind(&listen).await.map_err(Error::Network)?;
-    tracing::info!("Listening on {}", listen);
+    let Settings {
+        switching:
+            Switching {
+                mut switch_keys,
+                mut targets,
+                mut groups,
+                mut propagate_switch_keys,
+                mut layout,
+            },
+        rules,
+        mut profiles,
+        mut password,
+        mut acceptor,
+    } = settings;
+
+    // Shared with the monitor, so that reloaded rules apply to newly found devices as well.
+    let rules = Arc::new(Mutex::new(rules));
+    let mut monitor = Monitor::new({
+        let rules = rules.clone();
 
-    let mut monitor = Monitor::new();
+        move |properties| {
+            if rules.lock().unwrap().action(&Description::new(properties)) != Action::Ignore {
+                return true;
+            }
+
+            tracing::info!(name = ?properties.name(), "Ignoring device");
+            false
+        }
+    });
     let mut devices = Slab::<Device>::new();
-    let mut clients = Slab::<(Sender<_>, SocketAddr)>::new();
-    let mut current = 0;
-    let mut previous = 0;
+    // Clients are ordered by their name, which also determines the switching order.
+    let mut clients = BTreeMap::<String, Client>::new();
+    let mut current = Target::Local;
+    // The target subscribers have last been told about.
+    let mut announced = Target::Local;
+    let mut previous = Target::Local;
     let mut changed = false;
     let mut pressed_keys = HashSet::new();
+    // LEDs as last requested by the server's own display server.
+    let mut local_leds = HashMap::new();
+    // Latest contents copied on any of the targets, along with the target that has last been given them.
+    let mut contents = None::<String>;
+    let mut contents_target = Target::Local;
 
     let (events_sender, mut events_receiver) = mpsc::channel(1);
+    let (registrations_sender, mut registrations_receiver) = mpsc::channel(1);
+    let (reports_sender, mut reports_receiver) = mpsc::channel(1);
 
     loop {
+        // Fall back to the server itself if the focused client has gone away.
+        if !connected(&clients, &current) {
+            current = Target::Local;
+        }
+
+        if announced != current {
+            // Nobody might be subscribed, which is fine.
+            let _ = focus.send(current.to_string());
+            announced = current.clone();
+
+            if let Some((x, y)) = layout.enter(&current.to_string()) {
+                warp(&devices, &mut clients, &current, x, y);
+            }
+        }
+
+        sync_leds(&mut devices, &clients, &local_leds, &current);
+
+        // Hand the latest clipboard contents over to a newly focused target.
+        if contents_target != current {
+            if let Some(text) = &contents {
+                share(&clipboard, &mut clients, &current, text);
+            }
+
+            contents_target = current.clone();
+        }
+
         let event = async { events_receiver.recv().await.unwrap() };
+        let registration = async { registrations_receiver.recv().await.unwrap() };
+        let report = async { reports_receiver.recv().await.unwrap() };
+        let clipboard_changed = async {
+            match &mut clipboard {
+                Some(clipboard) => clipboard.changed().await,
+                None => future::pending().await,
+            }
+        };
 
         tokio::select! {
-            result = listener.accept() => {
+            _ = &mut shutdown => break,
+            Some(result) = connections.recv() => {
                 let (stream, addr) = result.map_err(Error::Network)?;
                 let acceptor = acceptor.clone();
-                let password = password.to_owned();
+                let password = password.clone();
+                let registrations_sender = registrations_sender.clone();
+                let reports_sender = reports_sender.clone();
 
-                // Remove dead clients.
-                clients.retain(|_, (client, _)| !client.is_closed());
-                if !clients.contains(current) {
-                    current = 0;
-                }
-
-                let init_updates = devices
-                    .iter()
-                    .map(|(id, device)| Update::CreateDevice {
-                        id,
-                        name: device.name.clone(),
-                        version: device.version,
-                        vendor: device.vendor,
-                        product: device.product,
-                        rel: device.rel.clone(),
-                        abs: device.abs.clone(),
-                        keys: device.keys.clone(),
-                        delay: device.delay,
-                        period: device.period,
-                    })
-                    .collect();
-
-                let (sender, receiver) = mpsc::channel(1);
-                clients.insert((sender, addr));
-
-                let span = tracing::info_span!("connection", addr = %addr);
+                let span = tracing::info_span!("connection", addr = %addr, name = field::Empty);
                 tokio::spawn(
                     async move {
                         tracing::info!("Connected");
 
-                        match client(init_updates, receiver, stream, acceptor, &password).await {
+                        match client(registrations_sender, reports_sender, stream, addr, acceptor, password.as_deref()).await {
                             Ok(()) => tracing::info!("Disconnected"),
                             Err(err) => tracing::error!("Disconnected: {}", err),
                         }
@@ -100,6 +233,214 @@ pub async fn run(
                     .instrument(span),
                 );
             }
+            Some(command) = commands.recv() => match command {
+                Command::Request(request, reply) => {
+                    let response = match request {
+                        Request::Clients => Response::Clients(
+                            clients
+                                .iter()
+                                .map(|(name, client)| ClientInfo {
+                                    name: name.clone(),
+                                    addr: client.addr,
+                                    dropped: client.sender.dropped(),
+                                    lag: client.sender.lag(),
+                                })
+                                .collect(),
+                        ),
+                        Request::Devices => Response::Devices(
+                            devices
+                                .iter()
+                                .map(|(id, device)| DeviceInfo {
+                                    id,
+                                    name: device.name.to_string_lossy().into_owned(),
+                                    vendor: device.vendor,
+                                    product: device.product,
+                                    version: device.version,
+                                    dropped: device.sender.dropped(),
+                                })
+                                .collect(),
+                        ),
+                        Request::Focus => Response::Focus(current.to_string()),
+                        Request::Switch { target: name } => match resolve(&clients, &groups, &name) {
+                            Some(next) => {
+                                release(&mut devices, &mut clients, &current);
+                                current = next;
+
+                                tracing::info!(name = %current, "Switched client on request");
+                                Response::Done
+                            }
+                            None => Response::Error(format!("Target {:?} is not connected", name)),
+                        },
+                        Request::Disconnect { name } => match clients.remove(&name) {
+                            Some(client) => {
+                                tracing::info!(name = %name, addr = %client.addr, "Disconnected client on request");
+                                Response::Done
+                            }
+                            None => Response::Error(format!("Client {:?} is not connected", name)),
+                        },
+                        // Reloads are handled before reaching the server task, as they need the loader.
+                        Request::Reload => Response::Error("Reloading is not supported here".to_owned()),
+                    };
+
+                    let _ = reply.send(response);
+                }
+                Command::Reload(settings, reply) => {
+                    let Settings {
+                        switching,
+                        rules: new_rules,
+                        profiles: new_profiles,
+                        password: new_password,
+                        acceptor: new_acceptor,
+                    } = *settings;
+
+                    // Held keys might not be bound or routed the same way anymore.
+                    release(&mut devices, &mut clients, &current);
+                    pressed_keys.clear();
+                    changed = false;
+
+                    switch_keys = switching.switch_keys;
+                    targets = switching.targets;
+                    groups = switching.groups;
+                    propagate_switch_keys = switching.propagate_switch_keys;
+                    layout = switching.layout;
+                    password = new_password;
+                    acceptor = new_acceptor;
+
+                    // The focused group might have different members now, or not exist at all.
+                    current = resolve(&clients, &groups, &current.to_string()).unwrap_or(Target::Local);
+
+                    clients.retain(|name, client| {
+                        let Profile { remap, pointer } = new_profiles.get(name).cloned().unwrap_or_default();
+                        client.pointer = Pointer::new(pointer);
+
+                        if remap == client.remap {
+                            return true;
+                        }
+
+                        // Remapping changes the keys devices have on the client, so they have to be created again.
+                        client.remap = remap;
+                        devices
+                            .iter()
+                            .filter(|(_, device)| device.visible(name))
+                            .all(|(id, device)| {
+                                push(name, client, Update::DestroyDevice { id })
+                                    && push(name, client, device.create(id, &client.remap))
+                            })
+                    });
+                    profiles = new_profiles;
+
+                    *rules.lock().unwrap() = new_rules;
+
+                    let mut rerouted = Vec::new();
+                    for (id, device) in devices.iter_mut() {
+                        let action = rules.lock().unwrap().action(&device.description);
+                        if action == Action::Ignore {
+                            // The device is destroyed once its task reports it as gone.
+                            if let Some(ungrab) = device.ungrab.take() {
+                                let _ = ungrab.send(());
+                                tracing::info!(id = %id, "Releasing ignored device");
+                            }
+
+                            continue;
+                        }
+
+                        let pinned = pinned(action);
+                        if pinned != device.pinned {
+                            rerouted.push((id, mem::replace(&mut device.pinned, pinned)));
+                        }
+                    }
+
+                    for (id, old) in rerouted {
+                        let device = &devices[id];
+                        clients.retain(|name, client| match (visible(&old, name), device.visible(name)) {
+                            (true, false) => push(name, client, Update::DestroyDevice { id }),
+                            (false, true) => push(name, client, device.create(id, &client.remap)),
+                            _ => true,
+                        });
+
+                        tracing::info!(id = %id, pinned = ?device.pinned.as_ref().map(ToString::to_string), "Rerouted device");
+                    }
+
+                    // Pick up devices that were previously ignored.
+                    monitor.rescan();
+
+                    // The new layout doesn't know where the pointer is.
+                    if let Some((x, y)) = layout.enter(&current.to_string()) {
+                        warp(&devices, &mut clients, &current, x, y);
+                    }
+
+                    tracing::info!("Reloaded configuration");
+                    let _ = reply.send(());
+                }
+            },
+            Registration { name, addr, capabilities, verified, sender: reply } = registration => {
+                // Remove dead clients.
+                clients.retain(|_, client| !client.sender.is_closed());
+
+                // Anyone knowing the password could otherwise take over the connection of another client.
+                if !verified && clients.contains_key(&name) {
+                    let _ = reply.send(None);
+                    continue;
+                }
+
+                let Profile { remap, pointer } = profiles.get(&name).cloned().unwrap_or_default();
+                let init_updates = devices
+                    .iter()
+                    .filter(|(_, device)| device.visible(&name))
+                    .map(|(id, device)| device.create(id, &remap))
+                    .collect::<Vec<_>>();
+
+                let (sender, receiver) = queue::queue(QUEUE_CAPACITY);
+                for update in init_updates {
+                    let _ = sender.push(update);
+                }
+
+                if reply.send(Some(receiver)).is_err() {
+                    continue;
+                }
+
+                // A client proving its name with a certificate replaces its previous connection.
+                let client = Client {
+                    sender,
+                    addr,
+                    leds: HashMap::new(),
+                    remap,
+                    pointer: Pointer::new(pointer),
+                    capabilities,
+                };
+
+                if let Some(client) = clients.insert(name.clone(), client) {
+                    tracing::warn!(name = %name, addr = %client.addr, "Replaced existing client connection");
+                }
+
+                tracing::info!(name = %name, addr = %addr, "Registered client");
+            }
+            (name, report) = report => match report {
+                Report::Led(LedEvent { led, on }) => {
+                    if let Some(client) = clients.get_mut(&name) {
+                        client.leds.insert(led, on);
+                    }
+                }
+                Report::Clipboard(text) => {
+                    tracing::debug!(name = %name, len = %text.len(), "Client clipboard changed");
+
+                    contents = Some(text);
+                    contents_target = Target::Client(name);
+                }
+                Report::Pong => {}
+            },
+            result = clipboard_changed => match result {
+                Ok(text) => {
+                    tracing::debug!(len = %text.len(), "Local clipboard changed");
+
+                    contents = Some(text);
+                    contents_target = Target::Local;
+                }
+                Err(err) => {
+                    tracing::warn!("Disabling clipboard sharing: {}", err);
+                    clipboard = None;
+                }
+            },
             result = monitor.read() => {
                 let mut interceptor = result.map_err(Error::Input)?;
 
@@ -112,25 +453,11 @@ pub async fn run(
                 let abs = interceptor.abs().collect::<HashMap<_,_>>();
                 let keys = interceptor.key().collect::<HashSet<_>>();
                 let repeat = interceptor.repeat();
+                let description = Description::new(&interceptor.properties());
+                let pinned = pinned(rules.lock().unwrap().action(&description));
 
-                for (_, (sender, _)) in &clients {
-                    let update = Update::CreateDevice {
-                        id,
-                        name: name.clone(),
-                        version: version.clone(),
-                        vendor: vendor.clone(),
-                        product: product.clone(),
-                        rel: rel.clone(),
-                        abs: abs.clone(),
-                        keys: keys.clone(),
-                        delay: repeat.delay,
-                        period: repeat.period,
-                    };
-
-                    let _ = sender.send(update).await;
-                }
-
-                let (interceptor_sender, mut interceptor_receiver) = mpsc::channel(32);
+                let (interceptor_sender, mut interceptor_receiver) = queue::queue(QUEUE_CAPACITY);
+                let (ungrab_sender, mut ungrab_receiver) = oneshot::channel();
                 devices.insert(Device {
                     name,
                     version,
@@ -142,34 +469,56 @@ pub async fn run(
                     delay: repeat.delay,
                     period: repeat.period,
                     sender: interceptor_sender,
+                    pressed: HashSet::new(),
+                    leds: HashMap::new(),
+                    pinned,
+                    description,
+                    ungrab: Some(ungrab_sender),
                 });
 
+                let device = &devices[id];
+                clients.retain(|name, client| !device.visible(name) || push(name, client, device.create(id, &client.remap)));
+
+                if let Some(recorder) = &recorder {
+                    recorder.record(device.create(id, &Remap::default()));
+                }
+
                 let events_sender = events_sender.clone();
                 tokio::spawn(async move {
-                    loop {
+                    let ungrabbed = loop {
                         tokio::select! {
                             event = interceptor.read() => {
                                 if event.is_err() | events_sender.send((id, event)).await.is_err() {
-                                    break;
+                                    break false;
                                 }
                             }
                             event = interceptor_receiver.recv() => {
                                 let event = match event {
                                     Some(event) => event,
-                                    None => break,
+                                    None => break false,
                                 };
 
                                 match interceptor.write(&event).await {
                                     Ok(()) => {},
                                     Err(err) => {
                                         let _ = events_sender.send((id, Err(err))).await;
-                                        break;
+                                        break false;
                                     }
                                 }
 
                                 tracing::trace!(id = %id, "Wrote an event to device");
                             }
+                            // The sender is also dropped along with the device, which the queue notices as well.
+                            result = &mut ungrab_receiver => break result.is_ok(),
                         }
+                    };
+
+                    // Ungrab the device before reporting it as gone, so that it can be grabbed again right away.
+                    drop(interceptor);
+
+                    if ungrabbed {
+                        let err = io::Error::new(ErrorKind::BrokenPipe, "Device ungrabbed");
+                        let _ = events_sender.send((id, Err(err))).await;
                     }
                 });
 
@@ -181,16 +530,42 @@ pub async fn run(
                     vendor = %device.vendor,
                     product = %device.product,
                     version = %device.version,
+                    pinned = ?device.pinned.as_ref().map(ToString::to_string),
                     "Registered new device"
                 );
             }
             (id, result) = event => match result {
+                Ok(event @ Event::Led(LedEvent { led, on })) => {
+                    if let Some(recorder) = &recorder {
+                        recorder.record(Update::Event { id, event });
+                    }
+
+                    local_leds.insert(led, on);
+                }
                 Ok(event) => {
+                    if let Some(recorder) = &recorder {
+                        recorder.record(Update::Event { id, event });
+                    }
+
+                    // Pinned devices ignore the focus altogether.
+                    if let Some(target) = &devices[id].pinned {
+                        send(&devices, &mut clients, target, id, [event]);
+                        continue;
+                    }
+
                     let mut press = false;
 
-                    if let Event::Key(KeyEvent { key, down }) = event {
-                        if switch_keys.contains(&key) {
+                    let mut down = false;
+
+                    if let Event::Key(KeyEvent { key, down: key_down }) = event {
+                        layout.key(key, key_down);
+
+                        let bound = switch_keys.contains(&key)
+                            || targets.values().any(|keys| keys.contains(&key));
+
+                        if bound {
                             press = true;
+                            down = key_down;
 
                             match down {
                                 true => pressed_keys.insert(key),
@@ -200,32 +575,91 @@ pub async fn run(
                     }
 
                     // Who to send this event to.
-                    let mut idx = current;
-
-                    if press {
-                        if pressed_keys.len() == switch_keys.len() {
-                            let exists = |idx| idx == 0 || clients.contains(idx - 1);
-                            loop {
-                                current = (current + 1) % (clients.len() + 1);
-                                if exists(current) {
-                                    break;
+                    let mut target = current.clone();
+
+                    // Only consider switching on key press, otherwise releasing a key of a larger
+                    // binding could trigger a smaller one.
+                    let next = if !down {
+                        None
+                    } else if pressed_keys == switch_keys {
+                        let next = match &current {
+                            Target::Local | Target::Group { .. } => clients.keys().next(),
+                            Target::Client(name) => clients
+                                .range::<str, _>((Bound::Excluded(name.as_str()), Bound::Unbounded))
+                                .next()
+                                .map(|(name, _)| name),
+                        };
+
+                        Some(next.map_or(Target::Local, |name| Target::Client(name.clone())))
+                    } else {
+                        targets
+                            .iter()
+                            .find(|(_, keys)| **keys == pressed_keys)
+                            .and_then(|(name, _)| {
+                                let next = resolve(&clients, &groups, name);
+                                if next.is_none() {
+                                    tracing::warn!(name = %name, "Target is not connected");
                                 }
-                            }
 
-                            previous = idx;
+                                next
+                            })
+                    };
+
+                    // Otherwise, follow the pointer across screen edges.
+                    let next = next.or_else(|| {
+                        let (axis, value) = match event {
+                            Event::Rel(RelEvent { axis: axis @ (RelAxis::X | RelAxis::Y), value }) => (axis, value),
+                            _ => return None,
+                        };
+
+                        // Follow the motion the way the target gets it.
+                        let scaled = match &current {
+                            Target::Client(name) => clients.get(name).and_then(|client| client.pointer.scale(axis, value)),
+                            Target::Local | Target::Group { .. } => None,
+                        };
+                        let value = scaled.unwrap_or_else(|| f64::from(value));
+                        let (dx, dy) = match axis {
+                            RelAxis::X => (value, 0.0),
+                            _ => (0.0, value),
+                        };
+
+                        layout
+                            .motion(&current.to_string(), dx, dy, |name| resolve(&clients, &groups, name).is_some())
+                            .and_then(|name| resolve(&clients, &groups, &name))
+                    });
+
+                    if let Some(next) = next {
+                        // Complete the frame the event might have been part of on the old target.
+                        send(&devices, &mut clients, &target, id, [Event::Sync(SyncEvent::All)]);
+
+                        // Switch keys are not tracked as pressed, so their releases are still going to be
+                        // delivered to the old target.
+                        release(&mut devices, &mut clients, &target);
+
+                        current = next;
+
+                        if press {
+                            previous = target.clone();
                             changed = true;
+                        }
 
-                            if current != 0 {
-                                tracing::info!(idx = %current, addr = %clients[current - 1].1, "Switched client");
-                            } else {
-                                tracing::info!(idx = %current, "Switched client");
+                        match &current {
+                            Target::Client(name) => {
+                                tracing::info!(name = %name, addr = %clients[name].addr, "Switched client");
                             }
-                        } else if changed {
-                            idx = previous;
+                            Target::Local | Target::Group { .. } => tracing::info!(name = %current, "Switched client"),
+                        }
 
-                            if pressed_keys.is_empty() {
-                                changed = false;
-                            }
+                        // The pointer is placed on the new target once the focus change is handled,
+                        // so the motion that crossed the edge is not needed anymore.
+                        if !press {
+                            continue;
+                        }
+                    } else if press && changed {
+                        target = previous.clone();
+
+                        if pressed_keys.is_empty() {
+                            changed = false;
                         }
                     }
 
@@ -233,41 +667,35 @@ pub async fn run(
                         continue;
                     }
 
-                    let events = [event]
-                        .into_iter()
-                        .chain(press.then_some(Event::Sync(SyncEvent::All)));
+                    if let Event::Key(KeyEvent { key, down }) = event {
+                        if !press {
+                            let pressed = &mut devices[id].pressed;
 
-                    // Index 0 - special case to keep the modular arithmetic above working.
-                    if idx == 0 {
-                        // We do a try_send() here rather than a "blocking" send in order to prevent deadlocks.
-                        // In this scenario, the interceptor task is sending events to the main task,
-                        // while the main task is simultaneously sending events back to the interceptor.
-                        // This creates a classic deadlock situation where both tasks are waiting for each other.
-                        for event in events {
-                            match devices[id].sender.try_send(event) {
-                                Ok(()) | Err(TrySendError::Closed(_)) => {},
-                                Err(TrySendError::Full(_)) => return Err(Error::Overflow),
-                            }
+                            match down {
+                                true => pressed.insert(key),
+                                false => pressed.remove(&key),
+                            };
                         }
-
-                        continue;
                     }
 
-                    for event in events {
-                        if clients[idx - 1].0.send(Update::Event { id, event }).await.is_err() {
-                            clients.remove(idx - 1);
+                    let events = [event]
+                        .into_iter()
+                        .chain(press.then_some(Event::Sync(SyncEvent::All)));
 
-                            if current == idx {
-                                current = 0;
-                            }
-                        }
-                    }
+                    send(&devices, &mut clients, &target, id, events);
                 }
                 Err(err) if err.kind() == ErrorKind::BrokenPipe => {
-                    for (_, (sender, _)) in &clients {
-                        let _ = sender.send(Update::DestroyDevice { id }).await;
+                    let device = &devices[id];
+                    clients.retain(|name, client| !device.visible(name) || push(name, client, Update::DestroyDevice { id }));
+
+                    if let Some(recorder) = &recorder {
+                        recorder.record(Update::DestroyDevice { id });
+                    }
+
+                    // The rules might have changed back since the device was released.
+                    if devices.remove(id).ungrab.is_none() {
+                        monitor.rescan();
                     }
-                    devices.remove(id);
 
                     tracing::info!(id = %id, "Destroyed device");
                 }
@@ -275,6 +703,316 @@ pub async fn run(
             }
         }
     }
+
+    tracing::info!("Shutting down");
+
+    // Devices are ungrabbed once their tasks notice they are gone, so wait for that to happen.
+    drop(devices);
+    drop(events_sender);
+    while events_receiver.recv().await.is_some() {}
+
+    Ok(())
+}
+
+// Shows the LED state of the target each device drives, turning off LEDs the state doesn't mention.
+fn sync_leds(
+    devices: &mut Slab<Device>,
+    clients: &BTreeMap<String, Client>,
+    local_leds: &HashMap<Led, bool>,
+    current: &Target,
+) {
+    for (id, device) in devices.iter_mut() {
+        // Pinned devices follow their own target instead of the focused one.
+        let leds = match device.pinned.as_ref().unwrap_or(current) {
+            Target::Local => local_leds,
+            Target::Client(name) => match clients.get(name) {
+                Some(client) => &client.leds,
+                None => continue,
+            },
+            // Broadcasting shows the LEDs of the first connected member.
+            Target::Group { members, .. } => {
+                match members.iter().find_map(|name| clients.get(name)) {
+                    Some(client) => &client.leds,
+                    None => continue,
+                }
+            }
+        };
+
+        let changed = leds
+            .iter()
+            .map(|(led, on)| (*led, *on))
+            .chain(
+                device
+                    .leds
+                    .keys()
+                    .filter(|led| !leds.contains_key(led))
+                    .map(|led| (*led, false)),
+            )
+            .filter(|(led, on)| device.leds.get(led) != Some(on))
+            .collect::<Vec<_>>();
+
+        for (led, on) in changed {
+            if device
+                .sender
+                .push(Event::Led(LedEvent { led, on }))
+                .is_err()
+            {
+                break;
+            }
+
+            tracing::debug!(id = %id, led = ?led, on = %on, "Set LED");
+            device.leds.insert(led, on);
+        }
+    }
+}
+
+// Groups take precedence over clients of the same name.
+fn resolve(
+    clients: &BTreeMap<String, Client>,
+    groups: &HashMap<String, Arc<[String]>>,
+    name: &str,
+) -> Option<Target> {
+    let target = match groups.get(name) {
+        Some(members) => Target::Group {
+            name: name.to_owned(),
+            members: members.clone(),
+        },
+        None => Target::new(name.to_owned()),
+    };
+
+    connected(clients, &target).then_some(target)
+}
+
+// Groups are considered connected as long as any of their members is.
+fn connected(clients: &BTreeMap<String, Client>, target: &Target) -> bool {
+    match target {
+        Target::Local => true,
+        Target::Client(name) => clients.contains_key(name),
+        Target::Group { members, .. } => members.iter().any(|name| clients.contains_key(name)),
+    }
+}
+
+// Releases everything that is held down on a target.
+fn release(devices: &mut Slab<Device>, clients: &mut BTreeMap<String, Client>, target: &Target) {
+    let released = devices
+        .iter_mut()
+        .filter(|(_, device)| !device.pressed.is_empty())
+        .map(|(id, device)| (id, device.pressed.drain().collect::<Vec<_>>()))
+        .collect::<Vec<_>>();
+
+    for (id, keys) in released {
+        let events = keys
+            .into_iter()
+            .map(|key| Event::Key(KeyEvent { key, down: false }))
+            .chain([Event::Sync(SyncEvent::All)]);
+
+        send(devices, clients, target, id, events);
+    }
+}
+
+// Sends events of a device to a target, removing the client if it has disconnected or fallen behind.
+fn send<T: IntoIterator<Item = Event>>(
+    devices: &Slab<Device>,
+    clients: &mut BTreeMap<String, Client>,
+    target: &Target,
+    id: usize,
+    events: T,
+) {
+    let names = match target {
+        Target::Client(name) => slice::from_ref(name),
+        Target::Group { members, .. } => members,
+        Target::Local => {
+            // Pushing never waits, which prevents a deadlock with the interceptor task,
+            // as it could be waiting for us to receive its events at the same time.
+            for event in events {
+                if let Ok(dropped) = devices[id].sender.push(event) {
+                    if dropped > 0 {
+                        tracing::warn!(id = %id, dropped = %dropped, "Device is lagging behind, dropped events");
+                    }
+                }
+            }
+
+            return;
+        }
+    };
+
+    let events = events.into_iter().collect::<Vec<_>>();
+
+    for name in names {
+        let remap = match clients.get(name) {
+            Some(client) => client.remap.clone(),
+            None => continue,
+        };
+
+        for event in events.iter().flat_map(|event| remap.apply(*event)) {
+            let client = match clients.get_mut(name) {
+                Some(client) => client,
+                None => break,
+            };
+
+            let event = match client.pointer.apply(event) {
+                Some(event) => event,
+                None => continue,
+            };
+
+            if !push(name, client, Update::Event { id, event }) {
+                clients.remove(name);
+                break;
+            }
+        }
+    }
+}
+
+// Places the pointer of a target at a position on its screen, by first moving it into the top left corner.
+// The position is only exact if the target doesn't accelerate the pointer on its own.
+fn warp(
+    devices: &Slab<Device>,
+    clients: &mut BTreeMap<String, Client>,
+    target: &Target,
+    x: i32,
+    y: i32,
+) {
+    // Any device that moves the pointer on the target will do.
+    let id = devices.iter().find(|(_, device)| {
+        device.pinned.is_none()
+            && device.rel.contains(&RelAxis::X)
+            && device.rel.contains(&RelAxis::Y)
+    });
+
+    let id = match id {
+        Some((id, _)) => id,
+        None => return,
+    };
+
+    let mut events = [(-WARP_DISTANCE, -WARP_DISTANCE), (x, y)]
+        .into_iter()
+        .flat_map(|(x, y)| {
+            [(RelAxis::X, x), (RelAxis::Y, y)]
+                .into_iter()
+                .filter(|(_, value)| *value != 0)
+                .map(|(axis, value)| Event::Rel(RelEvent { axis, value }))
+                .chain([Event::Sync(SyncEvent::All)])
+        });
+
+    // The events are already in pixels of the target, so they bypass its pointer settings.
+    match target {
+        Target::Local => {
+            for event in events {
+                let _ = devices[id].sender.push(event);
+            }
+        }
+        Target::Client(name) => {
+            if let Some(client) = clients.get(name) {
+                if !events.all(|event| push(name, client, Update::Event { id, event })) {
+                    clients.remove(name);
+                }
+            }
+        }
+        // Broadcast targets have no screen.
+        Target::Group { .. } => {}
+    }
+}
+
+// Gives clipboard contents to a target, removing clients that have disconnected or fallen behind.
+fn share(
+    clipboard: &Option<Clipboard>,
+    clients: &mut BTreeMap<String, Client>,
+    target: &Target,
+    text: &str,
+) {
+    let names = match target {
+        Target::Client(name) => slice::from_ref(name),
+        Target::Group { members, .. } => members,
+        Target::Local => {
+            // Failures are reported by the clipboard as changes.
+            if let Some(local) = clipboard {
+                local.set(text.to_owned());
+            }
+
+            return;
+        }
+    };
+
+    for name in names {
+        let client = match clients.get(name) {
+            Some(client) if client.capabilities.contains(Capabilities::CLIPBOARD) => client,
+            _ => continue,
+        };
+
+        if !push(name, client, Update::Clipboard(text.to_owned())) {
+            clients.remove(name);
+        }
+    }
+}
+
+// Queues an update for a client, returning whether the client should be kept.
+fn push(name: &str, client: &Client, update: Update) -> bool {
+    match client.sender.push(update) {
+        Ok(0) => {}
+        Ok(dropped) => {
+            tracing::warn!(name = %name, dropped = %dropped, "Client is lagging behind, dropped events");
+        }
+        Err(_) => return false,
+    }
+
+    let lag = client.sender.lag();
+    if lag > MAX_LAG {
+        tracing::warn!(name = %name, addr = %client.addr, lag = ?lag, "Disconnecting client that fell too far behind");
+        return false;
+    }
+
+    true
+}
+
+#[derive(Clone, PartialEq, Eq)]
+enum Target {
+    Local,
+    Client(String),
+    // Every event is sent to all connected members.
+    Group {
+        name: String,
+        members: Arc<[String]>,
+    },
+}
+
+impl Target {
+    fn new(name: String) -> Self {
+        if name == LOCAL_TARGET {
+            return Self::Local;
+        }
+
+        Self::Client(name)
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Local => write!(f, "{}", LOCAL_TARGET),
+            Self::Client(name) | Self::Group { name, .. } => write!(f, "{}", name),
+        }
+    }
+}
+
+struct Client {
+    sender: Producer<Update>,
+    addr: SocketAddr,
+    // LEDs of the client's devices, as reported by it.
+    leds: HashMap<Led, bool>,
+    remap: Arc<Remap>,
+    pointer: Pointer,
+    capabilities: Capabilities,
+}
+
+// Sent by a connection task to the main task once the client has been authenticated.
+struct Registration {
+    name: String,
+    addr: SocketAddr,
+    capabilities: Capabilities,
+    // Whether the name comes from a verified client certificate.
+    verified: bool,
+    // Gets None back if the name is already taken.
+    sender: oneshot::Sender<Option<Consumer<Update>>>,
 }
 
 struct Device {
@@ -287,53 +1025,267 @@ struct Device {
     keys: HashSet<Key>,
     delay: Option<i32>,
     period: Option<i32>,
-    sender: Sender<Event>,
+    sender: Producer<Event>,
+    // Keys and buttons held down on the current target, excluding switch keys.
+    pressed: HashSet<Key>,
+    // LEDs as last set on the device.
+    leds: HashMap<Led, bool>,
+    // Target receiving all events of the device regardless of the focus, the device only exists on it.
+    pinned: Option<Target>,
+    description: Description,
+    // Makes the device task ungrab the device, taken once that has been requested.
+    ungrab: Option<oneshot::Sender<()>>,
+}
+
+impl Device {
+    fn create(&self, id: usize, remap: &Remap) -> Update {
+        Update::CreateDevice {
+            id,
+            name: self.name.clone(),
+            version: self.version,
+            vendor: self.vendor,
+            product: self.product,
+            rel: self.rel.clone(),
+            abs: self.abs.clone(),
+            keys: remap.keys(&self.keys),
+            delay: self.delay,
+            period: self.period,
+        }
+    }
+
+    // Whether the device exists on the given client.
+    fn visible(&self, name: &str) -> bool {
+        visible(&self.pinned, name)
+    }
+}
+
+fn visible(pinned: &Option<Target>, name: &str) -> bool {
+    match pinned {
+        Some(Target::Client(pinned)) => pinned == name,
+        Some(Target::Local | Target::Group { .. }) => false,
+        None => true,
+    }
+}
+
+fn pinned(action: Action) -> Option<Target> {
+    match action {
+        Action::LocalOnly => Some(Target::Local),
+        Action::Pin(name) => Some(Target::new(name)),
+        Action::Ignore | Action::Forward => None,
+    }
 }
 
 #[derive(Error, Debug)]
 enum ClientError {
     #[error(transparent)]
     Io(#[from] io::Error),
-    #[error("Incompatible client version (got {client}, expected {server})")]
-    Version { server: Version, client: Version },
+    #[error("Incompatible client version (client supports {client}, server supports {server})")]
+    Version {
+        server: Handshake,
+        client: Handshake,
+    },
     #[error("Invalid password")]
     Auth,
+    #[error("Client certificate has no common name")]
+    CommonName,
+    #[error("Invalid client name {0:?}")]
+    Name(String),
+    #[error("Client name {0:?} is already taken")]
+    Taken(String),
+    #[error("Server exited")]
+    Closed,
     #[error(transparent)]
     Rand(#[from] rand::Error),
 }
 
 async fn client(
-    mut init_updates: VecDeque<Update>,
-    mut receiver: Receiver<Update>,
+    registrations: Sender<Registration>,
+    reports: Sender<(String, Report)>,
     stream: TcpStream,
+    addr: SocketAddr,
     acceptor: TlsAcceptor,
-    password: &str,
+    password: Option<&str>,
 ) -> Result<(), ClientError> {
     let stream = rkvm_net::timeout(rkvm_net::TLS_TIMEOUT, acceptor.accept(stream)).await?;
     tracing::info!("TLS connected");
 
+    // A certificate is only present if it has been verified, its common name then identifies the client.
+    let identity = match stream.get_ref().1.peer_certificates() {
+        Some([certificate, ..]) => {
+            Some(tls::common_name(certificate).ok_or(ClientError::CommonName)?)
+        }
+        _ => None,
+    };
+
     let mut stream = BufStream::with_capacity(1024, 1024, stream);
+    let verified = identity.is_some();
+    let (name, negotiated) = handshake(&mut stream, identity, addr, password).await?;
+
+    Span::current().record("name", name.as_str());
+
+    let (sender, receiver) = oneshot::channel();
+    registrations
+        .send(Registration {
+            name: name.clone(),
+            addr,
+            capabilities: negotiated.capabilities,
+            verified,
+            sender,
+        })
+        .await
+        .map_err(|_| ClientError::Closed)?;
+
+    let mut receiver = receiver
+        .await
+        .map_err(|_| ClientError::Closed)?
+        .ok_or_else(|| ClientError::Taken(name.clone()))?;
+
+    // Clients sending reports can do so at any time, so keep reading on the side.
+    let (mut reader, mut stream) = tokio::io::split(stream);
+    let (pongs_sender, mut pongs_receiver) = mpsc::channel(1);
+    let reporting = negotiated.capabilities.intersects(Capabilities::REPORTS);
+
+    let read = async {
+        loop {
+            let report = match reporting {
+                true => Report::decode(&mut reader).await?,
+                false => Pong::decode(&mut reader).await.map(|_| Report::Pong)?,
+            };
+
+            match report {
+                Report::Pong => {
+                    // The write side is gone, which is handled there.
+                    if pongs_sender.send(()).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                report => {
+                    if let Report::Led(event) = &report {
+                        tracing::debug!(led = ?event.led, on = %event.on, "Received LED report");
+                    }
+
+                    reports
+                        .send((name.clone(), report))
+                        .await
+                        .map_err(|_| ClientError::Closed)?;
+                }
+            }
+        }
+    };
+
+    let write = async {
+        let mut interval = time::interval(rkvm_net::PING_INTERVAL);
+        // Events of frames that haven't been terminated yet, by device.
+        let mut frames = HashMap::new();
+
+        loop {
+            let update = tokio::select! {
+                // Make sure pings have priority.
+                // The client could time out otherwise.
+                biased;
+
+                _ = interval.tick() => Some(Update::Ping),
+                recv = receiver.recv() => recv,
+            };
+
+            let update = match update {
+                Some(update) => update,
+                None => break,
+            };
+
+            let ping = matches!(update, Update::Ping);
+
+            let start = Instant::now();
+            rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
+                write(&mut stream, negotiated.version, &mut frames, update).await?;
+
+                // Batch everything that queued up in the meantime into a single flush.
+                while let Some(update) = receiver.try_recv() {
+                    write(&mut stream, negotiated.version, &mut frames, update).await?;
+                }
+
+                stream.flush().await?;
+
+                Ok(())
+            })
+            .await?;
+            let duration = start.elapsed();
+
+            if ping {
+                // Keeping these as debug because it's not as frequent as other updates.
+                tracing::debug!(duration = ?duration, "Sent ping");
+
+                let start = Instant::now();
+                rkvm_net::timeout(rkvm_net::READ_TIMEOUT, async {
+                    pongs_receiver.recv().await.ok_or_else(|| {
+                        io::Error::new(ErrorKind::UnexpectedEof, "Connection closed")
+                    })
+                })
+                .await?;
+                let duration = start.elapsed();
+
+                tracing::debug!(duration = ?duration, "Received pong");
+            }
+
+            tracing::trace!("Wrote updates");
+        }
+
+        Ok(())
+    };
+
+    tokio::select! {
+        result = read => result,
+        result = write => result,
+    }
+}
+
+// Negotiates the version and authenticates the client, returning the name identifying it.
+async fn handshake<S: AsyncRead + AsyncWrite + Send + Unpin>(
+    stream: &mut S,
+    identity: Option<String>,
+    addr: SocketAddr,
+    password: Option<&str>,
+) -> Result<(String, Negotiated), ClientError> {
+    // Clients send their version without waiting for ours, so we can answer legacy ones with theirs.
+    let peer = rkvm_net::timeout(rkvm_net::READ_TIMEOUT, async {
+        let version = Version::decode(&mut *stream).await?;
+        if version < Version::NEGOTIATION {
+            return Ok(Handshake::exact(version));
+        }
+
+        Handshake::decode(&mut *stream).await
+    })
+    .await?;
+
+    let negotiated = Handshake::CURRENT.negotiate(&peer);
 
     rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
-        Version::CURRENT.encode(&mut stream).await?;
+        if peer.max < Version::NEGOTIATION {
+            // Legacy clients only accept their own version.
+            let version = negotiated.map_or(Version::CURRENT, |negotiated| negotiated.version);
+            version.encode(&mut *stream).await?;
+        } else {
+            Version::CURRENT.encode(&mut *stream).await?;
+            Handshake::CURRENT.encode(&mut *stream).await?;
+        }
+
         stream.flush().await?;
 
         Ok(())
     })
     .await?;
 
-    let version = rkvm_net::timeout(rkvm_net::READ_TIMEOUT, Version::decode(&mut stream)).await?;
-    if version != Version::CURRENT {
-        return Err(ClientError::Version {
-            server: Version::CURRENT,
-            client: version,
-        });
-    }
+    let negotiated = negotiated.ok_or(ClientError::Version {
+        server: Handshake::CURRENT,
+        client: peer,
+    })?;
+
+    tracing::info!(version = %negotiated.version, "Negotiated protocol version");
 
     let challenge = AuthChallenge::generate().await?;
 
     rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
-        challenge.encode(&mut stream).await?;
+        challenge.encode(&mut *stream).await?;
         stream.flush().await?;
 
         Ok(())
@@ -341,14 +1293,20 @@ async fn client(
     .await?;
 
     let response =
-        rkvm_net::timeout(rkvm_net::READ_TIMEOUT, AuthResponse::decode(&mut stream)).await?;
-    let status = match response.verify(&challenge, password) {
+        rkvm_net::timeout(rkvm_net::READ_TIMEOUT, AuthResponse::decode(&mut *stream)).await?;
+    let passed = match (&identity, password) {
+        (Some(_), _) => true,
+        (None, Some(password)) => response.verify(&challenge, password),
+        (None, None) => false,
+    };
+
+    let status = match passed {
         true => AuthStatus::Passed,
         false => AuthStatus::Failed,
     };
 
     rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
-        status.encode(&mut stream).await?;
+        status.encode(&mut *stream).await?;
         stream.flush().await?;
 
         Ok(())
@@ -361,53 +1319,104 @@ async fn client(
 
     tracing::info!("Authenticated successfully");
 
-    let mut interval = time::interval(rkvm_net::PING_INTERVAL);
+    let announced = match negotiated.version >= Version::HELLO {
+        true => {
+            let Hello { name } =
+                rkvm_net::timeout(rkvm_net::READ_TIMEOUT, Hello::decode(&mut *stream)).await?;
+            Some(name)
+        }
+        false => None,
+    };
 
-    loop {
-        let recv = async {
-            match init_updates.pop_front() {
-                Some(update) => Some(update),
-                None => receiver.recv().await,
+    let name = match (identity, announced) {
+        (Some(identity), announced) => {
+            if let Some(announced) = announced.filter(|announced| *announced != identity) {
+                tracing::warn!(announced = %announced, "Client announced a name different from its certificate");
             }
-        };
-
-        let update = tokio::select! {
-            // Make sure pings have priority.
-            // The client could time out otherwise.
-            biased;
 
-            _ = interval.tick() => Some(Update::Ping),
-            recv = recv => recv,
-        };
+            identity
+        }
+        (None, Some(announced)) => announced,
+        // Older clients don't announce a name, they used to be addressed by their IP address.
+        (None, None) => addr.ip().to_string(),
+    };
 
-        let update = match update {
-            Some(update) => update,
-            None => break,
-        };
+    if name.is_empty() || name == LOCAL_TARGET {
+        return Err(ClientError::Name(name));
+    }
 
-        let start = Instant::now();
-        rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
-            update.encode(&mut stream).await?;
-            stream.flush().await?;
+    Ok((name, negotiated))
+}
 
-            Ok(())
-        })
-        .await?;
-        let duration = start.elapsed();
+// Maximum number of events sent in one frame, in case a device never terminates its frames.
+const MAX_FRAME_LEN: usize = 256;
 
-        if let Update::Ping = update {
-            // Keeping these as debug because it's not as frequent as other updates.
-            tracing::debug!(duration = ?duration, "Sent ping");
+// Writes an update without flushing, collecting events into frames if the client supports them.
+async fn write<T: AsyncWrite + Send + Unpin>(
+    stream: &mut T,
+    version: Version,
+    frames: &mut HashMap<usize, Vec<Event>>,
+    update: Update,
+) -> Result<(), io::Error> {
+    match update {
+        Update::Event { id, event } if version >= Version::FRAMES => {
+            let end = matches!(event, Event::Sync(SyncEvent::All));
 
-            let start = Instant::now();
-            rkvm_net::timeout(rkvm_net::READ_TIMEOUT, Pong::decode(&mut stream)).await?;
-            let duration = start.elapsed();
+            let events = frames.entry(id).or_default();
+            events.push(event);
 
-            tracing::debug!(duration = ?duration, "Received pong");
+            if end || events.len() >= MAX_FRAME_LEN {
+                let events = frames.remove(&id).unwrap();
+                Update::Frame { id, events }.encode(stream).await?;
+            }
         }
+        update => {
+            if let Update::DestroyDevice { id } = update {
+                frames.remove(&id);
+            }
 
-        tracing::trace!("Wrote an update");
+            update.encode(stream).await?;
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn names_legacy_clients_by_address() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let addr = "192.168.1.10:40000".parse().unwrap();
+
+        // Such clients send their version, answer the challenge and start reading updates right away.
+        let legacy = async {
+            assert!(Version::MIN < Version::HELLO);
+
+            Version::MIN.encode(&mut client).await.unwrap();
+            assert_eq!(Version::decode(&mut client).await.unwrap(), Version::MIN);
+
+            let challenge = AuthChallenge::decode(&mut client).await.unwrap();
+            challenge
+                .respond("password")
+                .encode(&mut client)
+                .await
+                .unwrap();
+
+            assert_eq!(
+                AuthStatus::decode(&mut client).await.unwrap(),
+                AuthStatus::Passed
+            );
+        };
+
+        let (result, ()) =
+            tokio::join!(handshake(&mut server, None, addr, Some("password")), legacy);
+
+        let (name, negotiated) = result.unwrap();
+        assert_eq!(name, "192.168.1.10");
+        assert_eq!(negotiated.version, Version::MIN);
+        assert_eq!(negotiated.capabilities, Capabilities::NONE);
+    }
+}
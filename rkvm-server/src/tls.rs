@@ -1,10 +1,16 @@
 use rustls_pemfile::Item;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::SystemTime;
 use std::{io, iter};
 use thiserror::Error;
 use tokio::fs;
-use tokio_rustls::rustls::{self, Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::rustls::server::{
+    AllowAnyAuthenticatedClient, ClientCertVerified, ClientCertVerifier, NoClientAuth,
+};
+use tokio_rustls::rustls::{
+    self, Certificate, CertificateError, DistinguishedName, PrivateKey, RootCertStore, ServerConfig,
+};
 use tokio_rustls::TlsAcceptor;
 
 #[derive(Error, Debug)]
@@ -19,7 +25,21 @@ pub enum Error {
     NoKeys,
 }
 
-pub async fn configure(certificate: &Path, key: &Path) -> Result<TlsAcceptor, Error> {
+// Which client certificates to accept.
+pub struct ClientAuth<'a> {
+    // CA bundle the client certificates have to be issued by.
+    pub ca: Option<&'a Path>,
+    // Client certificates trusted as they are.
+    pub certificates: &'a [PathBuf],
+    // Whether clients without a certificate are rejected.
+    pub mandatory: bool,
+}
+
+pub async fn configure(
+    certificate: &Path,
+    key: &Path,
+    client_auth: ClientAuth<'_>,
+) -> Result<TlsAcceptor, Error> {
     enum LoadedItem {
         Certificate(Vec<u8>),
         Key(Vec<u8>),
@@ -72,11 +92,108 @@ pub async fn configure(certificate: &Path, key: &Path) -> Result<TlsAcceptor, Er
 
     let key = key.ok_or(Error::NoKeys)?;
 
+    let verifier: Arc<dyn ClientCertVerifier> = if client_auth.ca.is_none()
+        && client_auth.certificates.is_empty()
+    {
+        NoClientAuth::boxed()
+    } else {
+        let ca = match client_auth.ca {
+            Some(path) => {
+                let mut store = RootCertStore::empty();
+                for certificate in read_certificates(path).await? {
+                    store.add(&certificate)?;
+                }
+
+                Some(AllowAnyAuthenticatedClient::new(store))
+            }
+            None => None,
+        };
+
+        let mut pinned = Vec::new();
+        for path in client_auth.certificates {
+            pinned.extend(read_certificates(path).await?);
+        }
+
+        // Clients pick their certificate based on these, so include issuers of pinned certificates too.
+        let mut subjects = ca
+            .as_ref()
+            .map(|ca| ca.client_auth_root_subjects().to_vec())
+            .unwrap_or_default();
+
+        for certificate in &pinned {
+            let (_, parsed) = x509_parser::parse_x509_certificate(&certificate.0)
+                .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+
+            subjects.push(parsed.issuer().as_raw().to_vec().into());
+        }
+
+        Arc::new(ClientVerifier {
+            ca,
+            pinned,
+            subjects,
+            mandatory: client_auth.mandatory,
+        })
+    };
+
     ServerConfig::builder()
         .with_safe_defaults()
-        .with_no_client_auth()
+        .with_client_cert_verifier(verifier)
         .with_single_cert(certificates, key)
         .map(Arc::new)
         .map(Into::into)
         .map_err(Into::into)
 }
+
+// Returns the common name of a certificate, which is used as the identity of clients.
+pub fn common_name(certificate: &Certificate) -> Option<String> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(&certificate.0).ok()?;
+    let name = certificate.subject().iter_common_name().next()?;
+
+    name.as_str().ok().map(ToOwned::to_owned)
+}
+
+async fn read_certificates(path: &Path) -> Result<Vec<Certificate>, Error> {
+    let data = fs::read(path).await?;
+    let certificates = rustls_pemfile::certs(&mut data.as_slice())?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+
+    Ok(certificates)
+}
+
+struct ClientVerifier {
+    ca: Option<AllowAnyAuthenticatedClient>,
+    pinned: Vec<Certificate>,
+    subjects: Vec<DistinguishedName>,
+    mandatory: bool,
+}
+
+impl ClientCertVerifier for ClientVerifier {
+    fn client_auth_mandatory(&self) -> bool {
+        self.mandatory
+    }
+
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        &self.subjects
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        // Pinned certificates are trusted as is, possession of their private key is still verified by rustls.
+        if self.pinned.contains(end_entity) {
+            return Ok(ClientCertVerified::assertion());
+        }
+
+        match &self.ca {
+            Some(ca) => ca.verify_client_cert(end_entity, intermediates, now),
+            None => Err(rustls::Error::InvalidCertificate(
+                CertificateError::UnknownIssuer,
+            )),
+        }
+    }
+}